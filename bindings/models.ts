@@ -0,0 +1,72 @@
+// このファイルは自動生成されています。`src/models.rs`を編集し、
+// `VVVST_UPDATE_BINDINGS=1 cargo test`で再生成してください。
+
+export type JsonValue = number | string | boolean | Array<JsonValue> | { [key in string]?: JsonValue } | null;
+
+export type SingingVoiceKey = string;
+
+export type RequestId = number;
+
+export type Response = { requestId: RequestId, payload: { Ok : JsonValue } | { Err : IpcError }, };
+
+export type IpcError = { code: ErrorCode, message: string, context: Array<string>, details?: JsonValue | null, };
+
+export type ErrorCode = "parseError" | "unsupportedRequest" | "unsupportedProtocol" | "invalidArgument" | "notFound" | "permissionDenied" | "cancelled" | "busy" | "io" | "internal";
+
+export type Request = { requestId: RequestId, inner: RequestInner, };
+
+export type RequestInner = { "type": "cancel", "payload": { requestId: RequestId, } } | { "type": "handshake", "payload": Handshake } | { "type": "batch", "payload": BatchRequest } | { "type": "getVersion" } | { "type": "getProjectName" } | { "type": "getConfig" } | { "type": "getProject" } | { "type": "setProject", "payload": string } | { "type": "setPhrases", "payload": Array<Phrase> } | { "type": "setVoices", "payload": { [key in SingingVoiceKey]?: string } } | { "type": "setVoiceChunk", "payload": VoiceChunk } | { "type": "getVoiceUploadStatus", "payload": SingingVoiceKey } | { "type": "showMessageDialog", "payload": ShowMessageDialog } | { "type": "showImportFileDialog", "payload": ShowImportFileDialog } | { "type": "showImportFilesDialog", "payload": ShowImportFileDialog } | { "type": "showFolderDialog", "payload": ShowFolderDialog } | { "type": "showSaveFileDialog", "payload": ShowSaveFileDialog } | { "type": "showQuestionDialog", "payload": ShowQuestionDialog } | { "type": "readFile", "payload": FileRef } | { "type": "writeFile", "payload": WriteFile } | { "type": "exportProject", "payload": ExportProjectOptions | null } | { "type": "importProject" } | { "type": "undo" } | { "type": "redo" } | { "type": "getHistory" } | { "type": "exportMix", "payload": ExportMix } | { "type": "renderAudio", "payload": RenderAudio };
+
+export type Event = { "type": "filesDropped", "payload": Array<PickedFile> } | { "type": "projectLoaded" } | { "type": "stateReloaded" };
+
+export type Handshake = { protocolVersion: number, capabilities?: Array<string>, };
+
+export type HandshakeResult = { protocolVersion: number, minProtocolVersion: number, pluginVersion: string, capabilities: Array<string>, };
+
+export type BatchRequest = { requests: Array<RequestInner>, concurrent?: boolean, };
+
+export type BatchResult = Array<{ Ok : JsonValue } | { Err : IpcError }>;
+
+export type ShowImportFileDialog = { title: string, name?: string | null, filters?: Array<string> | null, filterGroups?: Array<FileFilter>, startingDirectory?: string | null, purpose?: DialogPurpose | null, };
+
+export type DialogPurpose = "import" | "projectExport" | "audioExport";
+
+export type ShowFolderDialog = { title: string, startingDirectory?: string | null, purpose?: DialogPurpose | null, };
+
+export type FileFilter = { name: string, extensions: Array<string>, };
+
+export type ShowSaveFileDialog = { title: string, defaultName?: string | null, filters?: Array<FileFilter>, startingDirectory?: string | null, purpose?: DialogPurpose | null, };
+
+export type WriteFile = { file: FileRef, data: string, };
+
+export type PickedFile = { path: string, token: string, };
+
+export type ExportMix = { start?: number | null, end?: number | null, destination: MixDestination, };
+
+export type ExportProjectOptions = { pretty?: boolean, };
+
+export type HistoryStatus = { undoCount: number, redoCount: number, };
+
+export type MixDestination = "temporary" | "dialog";
+
+export type RenderAudio = { format: AudioFormat, sampleRate: number, bitDepth: BitDepth, channels: number, };
+
+export type AudioFormat = "wav" | "flac";
+
+export type BitDepth = "int16" | "int24" | "float32";
+
+export type FileRef = { token: string, name: string, } | { token: string, } | string;
+
+export type Phrase = { start: number, voice: SingingVoiceKey, };
+
+export type SetPhraseResult = { missingVoices: Array<SingingVoiceKey>, };
+
+export type VoiceChunk = { voice: SingingVoiceKey, index: number, totalChunks: number, data: string, };
+
+export type VoiceUploadStatus = { totalChunks: number, missingChunks: Array<number>, complete: boolean, };
+
+export type ShowMessageDialog = { type: DialogType, title: string, message: string, };
+
+export type DialogType = "none" | "info" | "warning" | "error" | "question";
+
+export type ShowQuestionDialog = { type: DialogType, title: string, message: string, buttons: Array<string>, cancelId?: number | null, defaultId?: number | null, };
@@ -0,0 +1,263 @@
+use anyhow::Context as _;
+use clap::{Parser, Subcommand};
+use std::collections::HashSet;
+use std::io::Write as _;
+use std::path::{Path, PathBuf};
+use vvvst_core::models::{BitDepth, SingingVoiceKey};
+use vvvst_core::state::SavedState;
+use vvvst_core::{mixer, wav};
+
+/// DAWに保存されたVVVSTの状態を扱うツール。
+#[derive(Parser)]
+#[command(version)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// 保存された状態をWAVに書き出す。
+    Render {
+        /// 状態のJSONファイル
+        state: PathBuf,
+        /// 書き出し先のWAVファイル
+        #[arg(short, long)]
+        output: PathBuf,
+        #[arg(long, default_value_t = 48000)]
+        sample_rate: u32,
+        /// int16、int24、float32のどれか
+        #[arg(long, default_value = "int16", value_parser = parse_bit_depth)]
+        bit_depth: BitDepth,
+        #[arg(long, default_value_t = 1)]
+        channels: u16,
+    },
+    /// 保存された状態の中身を一覧表示する。
+    Inspect {
+        /// 状態のJSONファイル
+        state: PathBuf,
+    },
+    /// 保存された状態から音声をWAVとして取り出す。
+    ExtractVoices {
+        /// 状態のJSONファイル
+        state: PathBuf,
+        /// 書き出し先のフォルダ
+        #[arg(short, long)]
+        output: PathBuf,
+        /// 取り出す音声のキー。指定しなかった場合は全て取り出す
+        #[arg(long)]
+        voice: Vec<String>,
+    },
+    /// 保存された状態からプロジェクト（.vvproj）を取り出す。
+    ExtractProject {
+        /// 状態のJSONファイル
+        state: PathBuf,
+        /// 書き出し先のファイル。指定しなかった場合は標準出力に書き出す
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+}
+
+fn main() -> anyhow::Result<()> {
+    match Cli::parse().command {
+        Command::Render {
+            state,
+            output,
+            sample_rate,
+            bit_depth,
+            channels,
+        } => {
+            let state = load_state(&state)?;
+            let wav = render(&state, sample_rate, bit_depth, channels)?;
+            std::fs::write(&output, wav)
+                .with_context(|| format!("failed to write {}", output.display()))?;
+        }
+        Command::Inspect { state } => {
+            let state = load_state(&state)?;
+            println!("project: {} bytes", state.project.len());
+            println!(
+                "voices: {} ({} bytes)",
+                state.voices.len(),
+                state.voices.values().map(Vec::len).sum::<usize>()
+            );
+            println!("phrases: {}", state.phrases.len());
+            println!(
+                "  {:>10}  {:>10}  {:>10}  voice",
+                "start", "duration", "size"
+            );
+            for phrase in &state.phrases {
+                let (duration, size) = match state.voices.get(&phrase.voice) {
+                    Some(voice) => (
+                        wav::duration(voice)
+                            .map_or_else(|| "invalid".to_string(), |d| format!("{:.3}s", d)),
+                        format!("{}", voice.len()),
+                    ),
+                    None => ("missing".to_string(), "-".to_string()),
+                };
+                println!(
+                    "  {:>10}  {:>10}  {:>10}  {}",
+                    format!("{:.3}s", phrase.start),
+                    duration,
+                    size,
+                    phrase.voice.0
+                );
+            }
+        }
+        Command::ExtractVoices {
+            state,
+            output,
+            voice,
+        } => {
+            let state = load_state(&state)?;
+            for key in &voice {
+                anyhow::ensure!(
+                    state.voices.contains_key(&SingingVoiceKey(key.clone())),
+                    "voice not found: {}",
+                    key
+                );
+            }
+            std::fs::create_dir_all(&output)
+                .with_context(|| format!("failed to create {}", output.display()))?;
+            let keys = state
+                .voices
+                .keys()
+                .map(|key| key.0.as_str())
+                .filter(|key| voice.is_empty() || voice.iter().any(|voice| voice == key));
+            for (key, name) in file_names(keys) {
+                let data = &state.voices[&SingingVoiceKey(key.to_string())];
+                let path = output.join(format!("{}.wav", name));
+                std::fs::write(&path, data)
+                    .with_context(|| format!("failed to write {}", path.display()))?;
+                println!("{}", path.display());
+            }
+        }
+        Command::ExtractProject { state, output } => {
+            let state = load_state(&state)?;
+            anyhow::ensure!(!state.project.is_empty(), "the state has no project");
+            match output {
+                Some(output) => std::fs::write(&output, &state.project)
+                    .with_context(|| format!("failed to write {}", output.display()))?,
+                None => std::io::stdout().write_all(state.project.as_bytes())?,
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn render(
+    state: &SavedState,
+    sample_rate: u32,
+    bit_depth: BitDepth,
+    channels: u16,
+) -> anyhow::Result<Vec<u8>> {
+    anyhow::ensure!((1..=2).contains(&channels), "channels must be 1 or 2");
+    let mix = mixer::mix(&state.phrases, &state.voices, sample_rate as f32)?;
+    for voice in &mix.invalid_voices {
+        eprintln!("warning: skipped invalid voice: {}", voice.0);
+    }
+    let samples = mixer::to_channels(mix.samples, channels);
+    Ok(wav::encode(&samples, sample_rate, channels, bit_depth))
+}
+
+fn load_state(path: &Path) -> anyhow::Result<SavedState> {
+    let json = std::fs::read_to_string(path)
+        .with_context(|| format!("failed to read {}", path.display()))?;
+    SavedState::from_json(&json).with_context(|| format!("failed to load {}", path.display()))
+}
+
+// 音声のキーはエディタが決めるので、ファイル名に使えない文字は置き換える
+fn file_name(key: &str) -> String {
+    key.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+// 置き換えた結果が同じになるキー（"a.b"と"a_b"など）は、上書きしないように番号を付ける。
+// WindowsとmacOSでは大文字と小文字を区別しないので、小文字にして比べる
+fn file_names<'a>(keys: impl IntoIterator<Item = &'a str>) -> Vec<(&'a str, String)> {
+    let mut keys = keys.into_iter().collect::<Vec<_>>();
+    keys.sort();
+    let mut used = HashSet::new();
+    keys.into_iter()
+        .map(|key| {
+            let base = file_name(key);
+            let mut name = base.clone();
+            let mut suffix = 1;
+            while !used.insert(name.to_lowercase()) {
+                suffix += 1;
+                name = format!("{}-{}", base, suffix);
+            }
+            (key, name)
+        })
+        .collect()
+}
+
+// エディタとの通信と同じ名前で指定できるようにする
+fn parse_bit_depth(value: &str) -> Result<BitDepth, String> {
+    serde_json::from_value(serde_json::Value::String(value.to_string()))
+        .map_err(|_| format!("unknown bit depth: {}", value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use vvvst_core::models::Phrase;
+
+    #[test]
+    fn colliding_file_names_get_a_suffix() {
+        assert_eq!(
+            file_names(["a_b", "a.b", "A_B", "a_b-2", "c"]),
+            vec![
+                ("A_B", "A_B".to_string()),
+                ("a.b", "a_b-2".to_string()),
+                ("a_b", "a_b-3".to_string()),
+                ("a_b-2", "a_b-2-2".to_string()),
+                ("c", "c".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn render_state() {
+        let voice = wav::encode(&[0.5; 4], 24000, 1, BitDepth::Int16);
+        let json = serde_json::json!({
+            "samples": { "a": voice, "broken": [0, 1, 2] },
+            "phrases": [
+                { "start": 0.0, "voice": "a" },
+                { "start": 0.0, "voice": "broken" },
+            ],
+        });
+        let state = SavedState::from_json(&json.to_string()).unwrap();
+
+        let rendered = render(&state, 24000, BitDepth::Int16, 2).unwrap();
+        // 壊れた音声は飛ばして、残りだけをステレオにする
+        assert_eq!(wav::duration(&rendered), Some(4.0 / 24000.0));
+        assert_eq!(rendered.len(), 44 + 4 * 2 * 2);
+
+        assert!(render(&state, 24000, BitDepth::Int16, 3).is_err());
+        let empty = SavedState {
+            phrases: vec![Phrase {
+                start: 0.0,
+                voice: SingingVoiceKey("missing".to_string()),
+            }],
+            ..Default::default()
+        };
+        assert_eq!(
+            render(&empty, 24000, BitDepth::Float32, 1).unwrap().len(),
+            58
+        );
+    }
+
+    #[test]
+    fn bit_depth_names() {
+        assert!(matches!(parse_bit_depth("int24"), Ok(BitDepth::Int24)));
+        assert!(parse_bit_depth("int8").is_err());
+    }
+}
@@ -0,0 +1,5 @@
+// プラグインとCLIで共有する、DAWやエディタに依存しない部分
+pub mod mixer;
+pub mod models;
+pub mod state;
+pub mod wav;
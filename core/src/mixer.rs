@@ -0,0 +1,158 @@
+use crate::models::{Phrase, SingingVoiceKey};
+use std::collections::HashMap;
+
+/// ミックスした音声。
+#[derive(Debug, Default)]
+pub struct Mix {
+    pub samples: Vec<f32>,
+    // WAVとして読めなかったので飛ばした音声。エディタから送られてきたものなので壊れていることがある
+    pub invalid_voices: Vec<SingingVoiceKey>,
+}
+
+/// フレーズを指定したサンプルレートのモノラル音声にミックスする。
+pub fn mix(
+    phrases: &[Phrase],
+    voices: &HashMap<SingingVoiceKey, impl AsRef<[u8]>>,
+    sample_rate: f32,
+) -> anyhow::Result<Mix> {
+    anyhow::ensure!(
+        sample_rate.is_finite() && sample_rate >= 1.0,
+        "invalid sample rate: {}",
+        sample_rate
+    );
+    let max_start = phrases
+        .iter()
+        .map(|phrase| phrase.start)
+        .filter(|start| start.is_finite())
+        .fold(0.0, f32::max);
+    let mut mix = Mix {
+        samples: vec![0.0; (max_start * sample_rate) as usize],
+        invalid_voices: vec![],
+    };
+    for phrase in phrases {
+        let Some(voice) = voices.get(&phrase.voice) else {
+            continue;
+        };
+        let samples = match decode(voice.as_ref(), sample_rate) {
+            Ok(samples) => samples,
+            Err(_) => {
+                if !mix.invalid_voices.contains(&phrase.voice) {
+                    mix.invalid_voices.push(phrase.voice.clone());
+                }
+                continue;
+            }
+        };
+        if !phrase.start.is_finite() {
+            continue;
+        }
+        let start = (phrase.start * sample_rate).floor() as isize;
+        let end = start + samples.len() as isize;
+
+        if end > mix.samples.len() as isize {
+            mix.samples.resize(end as usize, 0.0);
+        }
+        for (i, &sample) in samples.iter().enumerate() {
+            let frame = start + i as isize;
+            if frame < 0 {
+                continue;
+            }
+            let frame = &mut mix.samples[frame as usize];
+            if *frame > f32::MAX - sample {
+                *frame = f32::MAX;
+            } else if *frame < f32::MIN - sample {
+                *frame = f32::MIN;
+            } else {
+                *frame += sample;
+            }
+        }
+    }
+
+    Ok(mix)
+}
+
+// WAVを読み込んで、モノラル・指定したサンプルレートにする
+fn decode(voice: &[u8], sample_rate: f32) -> anyhow::Result<Vec<f32>> {
+    let mut wav = wav_io::reader::Reader::from_vec(voice.to_vec()).map_err(anyhow::Error::msg)?;
+    let header = wav.read_header().map_err(anyhow::Error::msg)?;
+    anyhow::ensure!(
+        header.sample_rate > 0 && (1..=2).contains(&header.channels),
+        "unsupported wav format"
+    );
+    let samples = wav.get_samples_f32().map_err(anyhow::Error::msg)?;
+    let samples = if header.channels == 1 {
+        samples
+    } else {
+        wav_io::utils::stereo_to_mono(samples)
+    };
+    Ok(wav_io::resample::linear(
+        samples,
+        1,
+        header.sample_rate,
+        sample_rate as u32,
+    ))
+}
+
+/// モノラルの音声を指定したチャンネル数にインターリーブする。
+pub fn to_channels(mix: Vec<f32>, channels: u16) -> Vec<f32> {
+    if channels == 1 {
+        return mix;
+    }
+    mix.iter()
+        .flat_map(|&sample| std::iter::repeat_n(sample, channels as usize))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn invalid_voices_are_skipped() {
+        let phrases = vec![
+            Phrase {
+                start: 0.5,
+                voice: SingingVoiceKey("broken".to_string()),
+            },
+            Phrase {
+                start: 1.0,
+                voice: SingingVoiceKey("missing".to_string()),
+            },
+        ];
+        let voices = HashMap::from([(SingingVoiceKey("broken".to_string()), vec![0, 1, 2])]);
+
+        let mix = mix(&phrases, &voices, 100.0).unwrap();
+        assert_eq!(mix.samples, vec![0.0; 100]);
+        assert_eq!(
+            mix.invalid_voices,
+            vec![SingingVoiceKey("broken".to_string())]
+        );
+    }
+
+    #[test]
+    fn phrases_are_mixed_at_their_start() {
+        let voice = crate::wav::encode(&[0.25, 0.5], 100, 1, crate::models::BitDepth::Float32);
+        let key = SingingVoiceKey("a".to_string());
+        let phrases = [0.01, 0.02].map(|start| Phrase {
+            start,
+            voice: key.clone(),
+        });
+        let voices = HashMap::from([(key, voice)]);
+
+        let mix = mix(&phrases, &voices, 100.0).unwrap();
+        assert_eq!(mix.samples, vec![0.0, 0.25, 0.75, 0.5]);
+        assert!(mix.invalid_voices.is_empty());
+    }
+
+    #[test]
+    fn invalid_sample_rate_is_an_error() {
+        let voices = HashMap::<_, Vec<u8>>::new();
+        assert!(mix(&[], &voices, 0.0).is_err());
+        assert!(mix(&[], &voices, f32::NAN).is_err());
+    }
+
+    #[test]
+    fn channels_are_interleaved() {
+        assert_eq!(to_channels(vec![1.0, 2.0], 2), vec![1.0, 1.0, 2.0, 2.0]);
+        assert_eq!(to_channels(vec![1.0, 2.0], 1), vec![1.0, 2.0]);
+    }
+}
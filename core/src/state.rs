@@ -0,0 +1,100 @@
+use crate::models::{Phrase, SingingVoiceKey};
+use anyhow::Context as _;
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use std::collections::HashMap;
+
+/// DAWに保存されたプラグインの状態。
+#[derive(Debug, Clone, Default)]
+pub struct SavedState {
+    pub voices: HashMap<SingingVoiceKey, Vec<u8>>,
+    pub phrases: Vec<Phrase>,
+    pub project: String,
+}
+
+impl SavedState {
+    /// 保存された状態のJSONを読み込む。
+    ///
+    /// nih_plugの`PluginState`（`fields`にJSON文字列として入っている）と、
+    /// `samples`・`phrases`・`project`を直接持つオブジェクトのどちらにも対応する。
+    pub fn from_json(json: &str) -> anyhow::Result<Self> {
+        let root: Value = serde_json::from_str(json).context("state is not valid JSON")?;
+        let (fields, encoded) = match root.get("fields") {
+            Some(fields) => (fields, true),
+            None => (&root, false),
+        };
+
+        Ok(Self {
+            voices: field(fields, "samples", encoded)?,
+            phrases: field(fields, "phrases", encoded)?,
+            project: field(fields, "project", encoded)?,
+        })
+    }
+}
+
+fn field<T: DeserializeOwned + Default>(
+    fields: &Value,
+    key: &str,
+    encoded: bool,
+) -> anyhow::Result<T> {
+    let Some(value) = fields.get(key) else {
+        return Ok(T::default());
+    };
+    let value = if encoded {
+        let value = value
+            .as_str()
+            .with_context(|| format!("field `{}` is not a string", key))?;
+        serde_json::from_str(value)
+    } else {
+        serde_json::from_value(value.clone())
+    };
+    value.with_context(|| format!("failed to parse field `{}`", key))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn plugin_state() {
+        // nih_plugのPluginStateでは、各フィールドがJSON文字列として入っている
+        let json = serde_json::json!({
+            "version": "0.1.0",
+            "params": {},
+            "fields": {
+                "samples": r#"{"a":[1,2,3]}"#,
+                "phrases": r#"[{"start":1.5,"voice":"a"}]"#,
+                "project": r#""{\"appVersion\":\"0.1.0\"}""#,
+            },
+        });
+        let state = SavedState::from_json(&json.to_string()).unwrap();
+        assert_eq!(
+            state.voices[&SingingVoiceKey("a".to_string())],
+            vec![1, 2, 3]
+        );
+        assert_eq!(state.phrases.len(), 1);
+        assert_eq!(state.phrases[0].start, 1.5);
+        assert_eq!(state.project, r#"{"appVersion":"0.1.0"}"#);
+    }
+
+    #[test]
+    fn bare_object() {
+        let json = serde_json::json!({
+            "phrases": [{ "start": 0.0, "voice": "a" }],
+            "project": "プロジェクト",
+        });
+        let state = SavedState::from_json(&json.to_string()).unwrap();
+        assert!(state.voices.is_empty());
+        assert_eq!(state.phrases[0].voice, SingingVoiceKey("a".to_string()));
+        assert_eq!(state.project, "プロジェクト");
+    }
+
+    #[test]
+    fn invalid_state() {
+        assert!(SavedState::from_json("not json").is_err());
+        let err = SavedState::from_json(r#"{"fields":{"phrases":1}}"#).unwrap_err();
+        assert!(err.to_string().contains("`phrases`"));
+        let err = SavedState::from_json(r#"{"phrases":"[]"}"#).unwrap_err();
+        assert!(err.to_string().contains("`phrases`"));
+    }
+}
@@ -0,0 +1,136 @@
+use crate::models::BitDepth;
+
+// 書き出し用の最小限のWAVエンコーダー。サンプルはチャンネルごとにインターリーブされているものとする。
+pub fn encode(samples: &[f32], sample_rate: u32, channels: u16, bit_depth: BitDepth) -> Vec<u8> {
+    let (format_tag, bytes_per_sample): (u16, u16) = match bit_depth {
+        // WAVE_FORMAT_PCM
+        BitDepth::Int16 => (1, 2),
+        BitDepth::Int24 => (1, 3),
+        // WAVE_FORMAT_IEEE_FLOAT
+        BitDepth::Float32 => (3, 4),
+    };
+
+    let data_len = (samples.len() * bytes_per_sample as usize) as u32;
+    let block_align = channels * bytes_per_sample;
+    // PCM以外ではfmtにcbSizeが必要で、factチャンクにフレーム数を書く
+    let is_float = format_tag == 3;
+    let fmt_len: u32 = if is_float { 18 } else { 16 };
+    let fact_len: u32 = if is_float { 12 } else { 0 };
+    let header_len = 12 + 8 + fmt_len + fact_len + 8;
+
+    let mut wav = Vec::with_capacity((header_len + data_len) as usize);
+    wav.extend_from_slice(b"RIFF");
+    wav.extend_from_slice(&(header_len - 8 + data_len).to_le_bytes());
+    wav.extend_from_slice(b"WAVE");
+
+    wav.extend_from_slice(b"fmt ");
+    wav.extend_from_slice(&fmt_len.to_le_bytes());
+    wav.extend_from_slice(&format_tag.to_le_bytes());
+    wav.extend_from_slice(&channels.to_le_bytes());
+    wav.extend_from_slice(&sample_rate.to_le_bytes());
+    wav.extend_from_slice(&(sample_rate * block_align as u32).to_le_bytes());
+    wav.extend_from_slice(&block_align.to_le_bytes());
+    wav.extend_from_slice(&(bytes_per_sample * 8).to_le_bytes());
+    if is_float {
+        wav.extend_from_slice(&0u16.to_le_bytes());
+
+        wav.extend_from_slice(b"fact");
+        wav.extend_from_slice(&4u32.to_le_bytes());
+        wav.extend_from_slice(&((samples.len() / channels as usize) as u32).to_le_bytes());
+    }
+
+    wav.extend_from_slice(b"data");
+    wav.extend_from_slice(&data_len.to_le_bytes());
+    for &sample in samples {
+        match bit_depth {
+            BitDepth::Int16 => {
+                wav.extend_from_slice(&(to_int(sample, 16) as i16).to_le_bytes());
+            }
+            BitDepth::Int24 => {
+                wav.extend_from_slice(&to_int(sample, 24).to_le_bytes()[..3]);
+            }
+            BitDepth::Float32 => wav.extend_from_slice(&sample.to_le_bytes()),
+        }
+    }
+    wav
+}
+
+/// -1.0〜1.0のサンプルを指定したビット数の整数に変換する。範囲外はクリップする。
+pub fn to_int(sample: f32, bits: u32) -> i32 {
+    let max = ((1i64 << (bits - 1)) - 1) as f32;
+    (sample.clamp(-1.0, 1.0) * max).round() as i32
+}
+
+/// WAVのヘッダーから長さ（秒）を求める。WAVとして読めない場合はNoneを返す。
+pub fn duration(data: &[u8]) -> Option<f32> {
+    if data.len() < 12 || &data[0..4] != b"RIFF" || &data[8..12] != b"WAVE" {
+        return None;
+    }
+    let mut byte_rate = None;
+    let mut offset = 12;
+    while offset + 8 <= data.len() {
+        let id = &data[offset..offset + 4];
+        let size = u32::from_le_bytes(data[offset + 4..offset + 8].try_into().ok()?) as usize;
+        let body = offset + 8;
+        match id {
+            b"fmt " if body + 12 <= data.len() => {
+                byte_rate = Some(u32::from_le_bytes(
+                    data[body + 8..body + 12].try_into().ok()?,
+                ));
+            }
+            b"data" => {
+                // 書き出し途中のファイルなどでサイズが実際より大きいことがあるので切り詰める
+                let size = size.min(data.len() - body);
+                return byte_rate
+                    .filter(|&byte_rate| byte_rate > 0)
+                    .map(|byte_rate| size as f32 / byte_rate as f32);
+            }
+            _ => {}
+        }
+        // チャンクは2バイト境界に揃えられている
+        offset = body + size + (size & 1);
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chunk<'a>(wav: &'a [u8], id: &[u8]) -> Option<&'a [u8]> {
+        let mut offset = 12;
+        while offset + 8 <= wav.len() {
+            let size = u32::from_le_bytes(wav[offset + 4..offset + 8].try_into().unwrap()) as usize;
+            if &wav[offset..offset + 4] == id {
+                return Some(&wav[offset + 8..offset + 8 + size]);
+            }
+            offset += 8 + size + (size & 1);
+        }
+        None
+    }
+
+    #[test]
+    fn float_has_cb_size_and_fact() {
+        let wav = encode(&[0.0, 0.5, -0.5, 1.0], 48000, 2, BitDepth::Float32);
+        let riff_len = u32::from_le_bytes(wav[4..8].try_into().unwrap()) as usize;
+        assert_eq!(riff_len, wav.len() - 8);
+
+        let fmt = chunk(&wav, b"fmt ").unwrap();
+        assert_eq!(fmt.len(), 18);
+        assert_eq!(u16::from_le_bytes([fmt[0], fmt[1]]), 3);
+        assert_eq!(u16::from_le_bytes([fmt[16], fmt[17]]), 0);
+        // 2チャンネルで4サンプルなので2フレーム
+        assert_eq!(chunk(&wav, b"fact").unwrap(), 2u32.to_le_bytes());
+        assert_eq!(chunk(&wav, b"data").unwrap().len(), 16);
+        assert_eq!(duration(&wav), Some(2.0 / 48000.0));
+    }
+
+    #[test]
+    fn pcm_has_plain_fmt() {
+        let wav = encode(&[0.0, 1.0], 44100, 1, BitDepth::Int16);
+        assert_eq!(wav.len(), 44 + 4);
+        assert_eq!(chunk(&wav, b"fmt ").unwrap().len(), 16);
+        assert!(chunk(&wav, b"fact").is_none());
+        assert_eq!(chunk(&wav, b"data").unwrap(), [0, 0, 0xff, 0x7f]);
+    }
+}
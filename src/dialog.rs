@@ -0,0 +1,185 @@
+use crate::error::{ErrorCode, IpcError};
+use crate::models::{DialogType, ShowQuestionDialog};
+use std::ops::Range;
+
+// rfdのダイアログは3つまでしかボタンを置けないので、それより多い場合はページに分けて表示する
+const MAX_BUTTONS: usize = 3;
+const MORE_BUTTON: &str = "その他…";
+
+/// 質問ダイアログを表示し、押されたボタンの番号を返す。
+/// 閉じられた場合はcancel_idを返す。
+pub async fn show_question_dialog(params: &ShowQuestionDialog) -> anyhow::Result<usize> {
+    let len = params.buttons.len();
+    anyhow::ensure!(
+        len > 0,
+        IpcError::new(
+            ErrorCode::InvalidArgument,
+            "At least one button is required"
+        )
+    );
+    for id in [params.cancel_id, params.default_id].into_iter().flatten() {
+        anyhow::ensure!(
+            id < len,
+            IpcError::new(
+                ErrorCode::InvalidArgument,
+                format!("button index {} is out of range (buttons: {})", id, len)
+            )
+        );
+    }
+    let cancel_id = params
+        .cancel_id
+        .unwrap_or_else(|| default_cancel_id(&params.buttons));
+
+    let pages = page_ids(len, params.default_id);
+    for (index, ids) in pages.iter().enumerate() {
+        let has_more = index + 1 < pages.len();
+        let mut labels = ids
+            .iter()
+            .map(|&id| params.buttons[id].clone())
+            .collect::<Vec<_>>();
+        if has_more {
+            labels.push(MORE_BUTTON.to_string());
+        }
+
+        let result = question_dialog(params, &labels).show().await;
+        let rfd::MessageDialogResult::Custom(label) = result else {
+            return Ok(cancel_id);
+        };
+        match labels.iter().position(|l| l == &label) {
+            Some(index) if index < ids.len() => return Ok(ids[index]),
+            Some(_) if has_more => continue,
+            _ => return Ok(cancel_id),
+        }
+    }
+    Ok(cancel_id)
+}
+
+// ボタンをページに分ける。最後のページ以外は「その他…」の分を空けておく
+fn pages(len: usize) -> Vec<Range<usize>> {
+    let mut pages = vec![];
+    let mut start = 0;
+    while len - start > MAX_BUTTONS {
+        pages.push(start..start + MAX_BUTTONS - 1);
+        start += MAX_BUTTONS - 1;
+    }
+    pages.push(start..len);
+    pages
+}
+
+// ページごとに表示するボタンの番号。rfdではデフォルトのボタンを指定できないが、
+// WindowsとmacOSでは最初のボタンにフォーカスが当たるので、default_idを先頭に出す
+fn page_ids(len: usize, default_id: Option<usize>) -> Vec<Vec<usize>> {
+    let order = default_id
+        .into_iter()
+        .chain((0..len).filter(|&id| Some(id) != default_id))
+        .collect::<Vec<_>>();
+    pages(len)
+        .into_iter()
+        .map(|page| order[page].to_vec())
+        .collect()
+}
+
+fn question_dialog(params: &ShowQuestionDialog, labels: &[String]) -> rfd::AsyncMessageDialog {
+    let dialog = rfd::AsyncMessageDialog::new()
+        .set_title(&params.title)
+        .set_description(&params.message);
+    let dialog = match params.r#type {
+        DialogType::Info => dialog.set_level(rfd::MessageLevel::Info),
+        DialogType::Warning => dialog.set_level(rfd::MessageLevel::Warning),
+        DialogType::Error => dialog.set_level(rfd::MessageLevel::Error),
+        _ => dialog,
+    };
+    dialog.set_buttons(match labels {
+        [ok] => rfd::MessageButtons::OkCustom(ok.clone()),
+        [ok, cancel] => rfd::MessageButtons::OkCancelCustom(ok.clone(), cancel.clone()),
+        [yes, no, cancel] => {
+            rfd::MessageButtons::YesNoCancelCustom(yes.clone(), no.clone(), cancel.clone())
+        }
+        _ => unreachable!(),
+    })
+}
+
+// Electronと同じく、キャンセル・いいえのボタンがあればそれを、なければ最初のボタンを使う
+fn default_cancel_id(buttons: &[String]) -> usize {
+    buttons
+        .iter()
+        .position(|button| {
+            matches!(
+                button.to_lowercase().as_str(),
+                "cancel" | "no" | "キャンセル" | "いいえ"
+            )
+        })
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn buttons(labels: &[&str]) -> Vec<String> {
+        labels.iter().map(|label| label.to_string()).collect()
+    }
+
+    #[test]
+    fn cancel_button_is_detected() {
+        assert_eq!(default_cancel_id(&buttons(&["保存", "キャンセル"])), 1);
+        assert_eq!(
+            default_cancel_id(&buttons(&["はい", "いいえ", "キャンセル"])),
+            1
+        );
+        assert_eq!(default_cancel_id(&buttons(&["OK", "Cancel"])), 1);
+        assert_eq!(default_cancel_id(&buttons(&["保存", "破棄"])), 0);
+    }
+
+    #[test]
+    fn buttons_are_paged_in_order() {
+        assert_eq!(pages(1), vec![0..1]);
+        assert_eq!(pages(3), vec![0..3]);
+        assert_eq!(pages(4), vec![0..2, 2..4]);
+        assert_eq!(pages(5), vec![0..2, 2..5]);
+        assert_eq!(pages(6), vec![0..2, 2..4, 4..6]);
+        // 全てのボタンがちょうど一度ずつ、元の順番で出てくる
+        for len in 1..20 {
+            let ids = pages(len).into_iter().flatten().collect::<Vec<_>>();
+            assert_eq!(ids, (0..len).collect::<Vec<_>>());
+            assert!(pages(len).iter().all(|page| page.len() <= MAX_BUTTONS));
+        }
+    }
+
+    #[test]
+    fn default_button_comes_first() {
+        assert_eq!(page_ids(3, None), vec![vec![0, 1, 2]]);
+        assert_eq!(page_ids(3, Some(2)), vec![vec![2, 0, 1]]);
+        assert_eq!(page_ids(5, Some(3)), vec![vec![3, 0], vec![1, 2, 4]]);
+
+        // 表示する順番が変わっても、押されたボタンは元の番号で返す
+        let labels = buttons(&["保存", "破棄", "キャンセル", "別名で保存"]);
+        let pages = page_ids(labels.len(), Some(3));
+        let shown = pages
+            .iter()
+            .map(|ids| {
+                ids.iter()
+                    .map(|&id| labels[id].as_str())
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            shown,
+            vec![vec!["別名で保存", "保存"], vec!["破棄", "キャンセル"]]
+        );
+        assert_eq!(pages[0][0], 3);
+        assert_eq!(pages[1][1], 2);
+
+        for len in 1..10 {
+            for default_id in 0..len {
+                let mut ids = page_ids(len, Some(default_id))
+                    .into_iter()
+                    .flatten()
+                    .collect::<Vec<_>>();
+                assert_eq!(ids[0], default_id);
+                ids.sort();
+                assert_eq!(ids, (0..len).collect::<Vec<_>>());
+            }
+        }
+    }
+}
@@ -0,0 +1,65 @@
+use nih_plug::prelude::{Editor, GuiContext, ParentWindowHandle};
+use std::any::Any;
+use std::sync::Arc;
+
+// エディタのウィンドウが閉じられたときに後片付けをする。
+// nih_plugはウィンドウを閉じるとspawnが返したハンドルを捨てるので、それに合わせて呼ぶ。
+pub struct ClosableEditor<E> {
+    editor: E,
+    on_close: Arc<dyn Fn() + Send + Sync>,
+}
+
+impl<E: Editor> ClosableEditor<E> {
+    pub fn new(editor: E, on_close: impl Fn() + Send + Sync + 'static) -> Self {
+        Self {
+            editor,
+            on_close: Arc::new(on_close),
+        }
+    }
+}
+
+struct CloseGuard {
+    handle: Option<Box<dyn Any + Send>>,
+    on_close: Arc<dyn Fn() + Send + Sync>,
+}
+
+impl Drop for CloseGuard {
+    fn drop(&mut self) {
+        // 先にウィンドウを閉じて、イベントループがもう動かないようにしてから片付ける
+        drop(self.handle.take());
+        (self.on_close)();
+    }
+}
+
+impl<E: Editor> Editor for ClosableEditor<E> {
+    fn spawn(
+        &self,
+        parent: ParentWindowHandle,
+        context: Arc<dyn GuiContext>,
+    ) -> Box<dyn Any + Send> {
+        Box::new(CloseGuard {
+            handle: Some(self.editor.spawn(parent, context)),
+            on_close: Arc::clone(&self.on_close),
+        })
+    }
+
+    fn size(&self) -> (u32, u32) {
+        self.editor.size()
+    }
+
+    fn set_scale_factor(&self, factor: f32) -> bool {
+        self.editor.set_scale_factor(factor)
+    }
+
+    fn param_value_changed(&self, id: &str, normalized_value: f32) {
+        self.editor.param_value_changed(id, normalized_value)
+    }
+
+    fn param_modulation_changed(&self, id: &str, modulation_offset: f32) {
+        self.editor.param_modulation_changed(id, modulation_offset)
+    }
+
+    fn param_values_changed(&self) {
+        self.editor.param_values_changed()
+    }
+}
@@ -0,0 +1,111 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use ts_rs::TS;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, TS)]
+#[serde(rename_all = "camelCase")]
+pub enum ErrorCode {
+    ParseError,
+    UnsupportedRequest,
+    UnsupportedProtocol,
+    InvalidArgument,
+    NotFound,
+    PermissionDenied,
+    Cancelled,
+    Busy,
+    Io,
+    Internal,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, TS)]
+#[serde(rename_all = "camelCase")]
+pub struct IpcError {
+    pub code: ErrorCode,
+    pub message: String,
+    // 原因となったエラーのメッセージ（外側から順）
+    #[serde(default)]
+    pub context: Vec<String>,
+    // Resultのエラーとして持ち回るので、大きくならないようにBoxにする
+    #[serde(default)]
+    #[ts(optional = nullable)]
+    pub details: Option<Box<Value>>,
+}
+
+impl IpcError {
+    pub fn new(code: ErrorCode, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+            context: vec![],
+            details: None,
+        }
+    }
+
+    pub fn with_details(mut self, details: Value) -> Self {
+        self.details = Some(Box::new(details));
+        self
+    }
+}
+
+impl std::fmt::Display for IpcError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for IpcError {}
+
+impl From<anyhow::Error> for IpcError {
+    fn from(err: anyhow::Error) -> Self {
+        let context = err.chain().skip(1).map(|cause| cause.to_string()).collect();
+
+        // 外側から順に、分類できるエラーを探す
+        for cause in err.chain() {
+            if let Some(ipc_error) = cause.downcast_ref::<IpcError>() {
+                return IpcError {
+                    message: err.to_string(),
+                    context,
+                    ..ipc_error.clone()
+                };
+            }
+        }
+        let code = err
+            .chain()
+            .find_map(|cause| {
+                if let Some(io_error) = cause.downcast_ref::<std::io::Error>() {
+                    Some(match io_error.kind() {
+                        std::io::ErrorKind::NotFound => ErrorCode::NotFound,
+                        std::io::ErrorKind::PermissionDenied => ErrorCode::PermissionDenied,
+                        _ => ErrorCode::Io,
+                    })
+                } else if cause.is::<serde_json::Error>() {
+                    Some(ErrorCode::ParseError)
+                } else if cause.is::<base64::DecodeError>() {
+                    Some(ErrorCode::InvalidArgument)
+                } else if cause.is::<std::env::VarError>() {
+                    Some(ErrorCode::NotFound)
+                } else {
+                    None
+                }
+            })
+            .unwrap_or(ErrorCode::Internal);
+
+        IpcError {
+            code,
+            message: err.to_string(),
+            context,
+            details: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn error_is_small() {
+        // Result<_, IpcError>を返しても大きくならないように（clippy::result_large_errの既定値は128バイト）
+        assert!(std::mem::size_of::<IpcError>() <= 128);
+    }
+}
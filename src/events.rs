@@ -0,0 +1,35 @@
+use crate::models::{Event, Handshake};
+use std::sync::{mpsc, Arc, Mutex as StdMutex};
+use tracing::info;
+
+// プラグイン側からエディタに送るイベント。
+// Handshakeで対応していると宣言されたイベントだけを送る。
+#[derive(Clone)]
+pub struct EventSender {
+    sender: Arc<mpsc::Sender<Event>>,
+    editor_info: Arc<StdMutex<Option<Handshake>>>,
+}
+
+impl EventSender {
+    pub fn new(sender: mpsc::Sender<Event>, editor_info: Arc<StdMutex<Option<Handshake>>>) -> Self {
+        Self {
+            sender: Arc::new(sender),
+            editor_info,
+        }
+    }
+
+    pub fn send(&self, event: Event) {
+        let event_type = event.event_type();
+        let accepted = self
+            .editor_info
+            .lock()
+            .unwrap()
+            .as_ref()
+            .is_some_and(|info| info.capabilities.iter().any(|c| c == event_type));
+        if !accepted {
+            info!("editor does not handle {} event, skipping", event_type);
+            return;
+        }
+        let _ = self.sender.send(event);
+    }
+}
@@ -0,0 +1,34 @@
+use flacenc::component::BitRepr;
+use flacenc::error::Verify;
+use vvvst_core::wav::to_int;
+
+// FLACは浮動小数点数のサンプルを扱えないので、整数に変換してからエンコードする。
+pub fn encode(
+    samples: &[f32],
+    sample_rate: u32,
+    channels: u16,
+    bits_per_sample: u32,
+) -> anyhow::Result<Vec<u8>> {
+    let samples = samples
+        .iter()
+        .map(|&sample| to_int(sample, bits_per_sample))
+        .collect::<Vec<_>>();
+
+    let config = flacenc::config::Encoder::default()
+        .into_verified()
+        .map_err(|(_, err)| anyhow::anyhow!("invalid FLAC encoder config: {:?}", err))?;
+    let source = flacenc::source::MemSource::from_samples(
+        &samples,
+        channels as usize,
+        bits_per_sample as usize,
+        sample_rate as usize,
+    );
+    let stream = flacenc::encode_with_fixed_block_size(&config, source, config.block_size)
+        .map_err(|err| anyhow::anyhow!("failed to encode FLAC: {:?}", err))?;
+
+    let mut sink = flacenc::bitsink::ByteSink::new();
+    stream
+        .write(&mut sink)
+        .map_err(|err| anyhow::anyhow!("failed to write FLAC: {:?}", err))?;
+    Ok(sink.as_slice().to_vec())
+}
@@ -0,0 +1,291 @@
+use crate::models::HistoryStatus;
+use serde::{Deserialize, Serialize, Serializer};
+use std::collections::VecDeque;
+
+// メモリ上で持つ履歴の上限
+const MAX_ENTRIES: usize = 100;
+const MAX_BYTES: usize = 16 * 1024 * 1024;
+// DAWのプロジェクトに保存する履歴の上限。保存のたびに書き出されるので、メモリ上よりずっと小さくする
+const PERSISTED_MAX_ENTRIES: usize = 20;
+const PERSISTED_MAX_BYTES: usize = 256 * 1024;
+
+// projectの[start, start + removed.len())をinsertedに置き換える変更。startはバイト単位。
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Delta {
+    start: usize,
+    removed: String,
+    inserted: String,
+}
+
+impl Delta {
+    fn new(before: &str, after: &str) -> Self {
+        let prefix = before
+            .char_indices()
+            .zip(after.chars())
+            .find(|((_, a), b)| a != b)
+            .map_or(before.len().min(after.len()), |((index, _), _)| index);
+        // 前方と重ならない範囲で、後ろから一致している部分を探す
+        let max_suffix = before.len().min(after.len()) - prefix;
+        let suffix = before[prefix..]
+            .chars()
+            .rev()
+            .zip(after[prefix..].chars().rev())
+            .take_while(|(a, b)| a == b)
+            .map(|(a, _)| a.len_utf8())
+            .scan(0, |len, char_len| {
+                *len += char_len;
+                Some(*len)
+            })
+            .take_while(|&len| len <= max_suffix)
+            .last()
+            .unwrap_or(0);
+
+        Self {
+            start: prefix,
+            removed: before[prefix..before.len() - suffix].to_string(),
+            inserted: after[prefix..after.len() - suffix].to_string(),
+        }
+    }
+
+    fn size(&self) -> usize {
+        self.removed.len() + self.inserted.len()
+    }
+
+    // fromのある場所をtoに置き換える。historyの外でprojectが変わっていて一致しない場合はNone。
+    fn replace(project: &str, start: usize, from: &str, to: &str) -> Option<String> {
+        let end = start.checked_add(from.len())?;
+        if project.get(start..end)? != from {
+            return None;
+        }
+        Some([&project[..start], to, &project[end..]].concat())
+    }
+
+    fn apply(&self, project: &str) -> Option<String> {
+        Self::replace(project, self.start, &self.removed, &self.inserted)
+    }
+
+    fn revert(&self, project: &str) -> Option<String> {
+        Self::replace(project, self.start, &self.inserted, &self.removed)
+    }
+}
+
+/// プロジェクトの元に戻す・やり直しの履歴。
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct History {
+    undo: VecDeque<Delta>,
+    redo: Vec<Delta>,
+}
+
+// 保存するときの形。元に戻す方を優先して、どちらも次に使うもの（末尾）から上限まで残す
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct PersistedHistory<'a> {
+    undo: Vec<&'a Delta>,
+    redo: Vec<&'a Delta>,
+}
+
+impl Serialize for History {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut entries = PERSISTED_MAX_ENTRIES;
+        let mut bytes = PERSISTED_MAX_BYTES;
+        PersistedHistory {
+            undo: newest(self.undo.iter(), &mut entries, &mut bytes),
+            redo: newest(self.redo.iter(), &mut entries, &mut bytes),
+        }
+        .serialize(serializer)
+    }
+}
+
+// 末尾から、残りの件数・バイト数に収まるだけ取り出す
+fn newest<'a>(
+    deltas: impl DoubleEndedIterator<Item = &'a Delta>,
+    entries: &mut usize,
+    bytes: &mut usize,
+) -> Vec<&'a Delta> {
+    let mut kept = deltas
+        .rev()
+        .take_while(|delta| {
+            if *entries == 0 || delta.size() > *bytes {
+                return false;
+            }
+            *entries -= 1;
+            *bytes -= delta.size();
+            true
+        })
+        .collect::<Vec<_>>();
+    kept.reverse();
+    kept
+}
+
+impl History {
+    pub fn record(&mut self, before: &str, after: &str) {
+        // 空のプロジェクトは新しいインスタンスでエディタが最初に送ってくる前の状態で、
+        // 戻すとエディタからもエクスポートからも読めなくなるので記録しない
+        if before == after || before.is_empty() {
+            return;
+        }
+        self.undo.push_back(Delta::new(before, after));
+        self.redo.clear();
+
+        let mut size = self.undo.iter().map(Delta::size).sum::<usize>();
+        while self.undo.len() > MAX_ENTRIES || (size > MAX_BYTES && self.undo.len() > 1) {
+            if let Some(delta) = self.undo.pop_front() {
+                size -= delta.size();
+            }
+        }
+    }
+
+    /// 一つ前のプロジェクトを返す。戻せない場合はNone。
+    pub fn undo(&mut self, project: &str) -> Option<String> {
+        let delta = self.undo.pop_back()?;
+        match delta.revert(project) {
+            // 以前のバージョンで記録された、空のプロジェクトへの変更
+            Some(previous) if previous.is_empty() => {
+                self.undo.clear();
+                None
+            }
+            Some(previous) => {
+                self.redo.push(delta);
+                Some(previous)
+            }
+            None => {
+                self.clear();
+                None
+            }
+        }
+    }
+
+    /// 元に戻したプロジェクトをやり直す。やり直せない場合はNone。
+    pub fn redo(&mut self, project: &str) -> Option<String> {
+        let delta = self.redo.pop()?;
+        match delta.apply(project) {
+            Some(next) => {
+                self.undo.push_back(delta);
+                Some(next)
+            }
+            None => {
+                self.clear();
+                None
+            }
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.undo.clear();
+        self.redo.clear();
+    }
+
+    pub fn status(&self) -> HistoryStatus {
+        HistoryStatus {
+            undo_count: self.undo.len(),
+            redo_count: self.redo.len(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(history: &History) -> History {
+        serde_json::from_str(&serde_json::to_string(history).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn multibyte_round_trip() {
+        let versions = ["ドレミ", "ドレミファ", "ドソミファ", "ソミファ🎵"];
+        let mut history = History::default();
+        for pair in versions.windows(2) {
+            history.record(pair[0], pair[1]);
+        }
+        let mut project = versions[3].to_string();
+        project = history.undo(&project).unwrap();
+        assert_eq!(project, versions[2]);
+
+        let mut history = round_trip(&history);
+        assert_eq!(history.status().undo_count, 2);
+        assert_eq!(history.status().redo_count, 1);
+        project = history.undo(&project).unwrap();
+        assert_eq!(project, versions[1]);
+        project = history.undo(&project).unwrap();
+        assert_eq!(project, versions[0]);
+        for version in &versions[1..] {
+            project = history.redo(&project).unwrap();
+            assert_eq!(&project, version);
+        }
+    }
+
+    #[test]
+    fn memory_is_trimmed() {
+        let mut history = History::default();
+        let mut project = "x".to_string();
+        for i in 0..MAX_ENTRIES + 10 {
+            let next = format!("{}{}", project, i % 10);
+            history.record(&project, &next);
+            project = next;
+        }
+        assert_eq!(history.status().undo_count, MAX_ENTRIES);
+        // 古いものから消えるので、最後の変更は戻せる
+        assert_eq!(
+            history.undo(&project).unwrap(),
+            project[..project.len() - 1]
+        );
+    }
+
+    #[test]
+    fn persisted_history_is_capped() {
+        let mut history = History::default();
+        let mut project = "x".to_string();
+        for _ in 0..PERSISTED_MAX_ENTRIES + 5 {
+            let next = format!("{}あ", project);
+            history.record(&project, &next);
+            project = next;
+        }
+        project = history.undo(&project).unwrap();
+
+        // メモリ上は全て残っている
+        assert_eq!(history.status().undo_count, PERSISTED_MAX_ENTRIES + 4);
+        let mut restored = round_trip(&history);
+        let status = restored.status();
+        assert_eq!(status.undo_count, PERSISTED_MAX_ENTRIES);
+        assert_eq!(status.redo_count, 0);
+        // 残ったのは新しい方なので、そのまま使える
+        let undone = restored.undo(&project).unwrap();
+        assert_eq!(
+            undone,
+            project[.."x".len() + "あ".len() * (PERSISTED_MAX_ENTRIES + 3)]
+        );
+    }
+
+    #[test]
+    fn persisted_history_is_capped_by_bytes() {
+        let large = "あ".repeat(PERSISTED_MAX_BYTES / 3 / 2 + 1);
+        let mut history = History::default();
+        history.record("a", &large);
+        history.record(&large, "a");
+        history.record("a", "small");
+
+        // 大きい変更が上限を超えたところで止まり、それより古いものは保存しない
+        let restored = round_trip(&history);
+        assert_eq!(restored.status().undo_count, 2);
+        assert!(serde_json::to_string(&history).unwrap().len() < PERSISTED_MAX_BYTES * 2);
+    }
+
+    #[test]
+    fn empty_project_is_never_restored() {
+        let mut history = History::default();
+        history.record("", "project");
+        assert_eq!(history.status().undo_count, 0);
+        assert_eq!(history.undo("project"), None);
+
+        // 以前のバージョンで保存された履歴には空のプロジェクトへの変更が残っている
+        let mut history = History::default();
+        history.undo.push_back(Delta::new("", "project"));
+        history.record("project", "project 2");
+        assert_eq!(history.undo("project 2").unwrap(), "project");
+        assert_eq!(history.undo("project"), None);
+        assert_eq!(history.status().undo_count, 0);
+    }
+}
@@ -1,28 +1,86 @@
+mod dialog;
+mod editor;
+mod error;
+mod events;
+mod flac;
+mod history;
 mod models;
+mod project;
+mod recovery;
+mod remix;
+mod sandbox;
+mod settings;
+mod tasks;
+mod upload;
 mod utils;
 use base64::{engine::general_purpose::STANDARD as base64, Engine as _};
+use editor::ClosableEditor;
+use error::{ErrorCode, IpcError};
+use events::EventSender;
+use history::History;
 use include_dir::{include_dir, Dir};
 use nih_plug::prelude::*;
 use nih_plug_webview::*;
+use remix::RemixScheduler;
+use sandbox::{Access, FileAccess};
 use serde_json::Value;
 use std::borrow::Cow;
 use std::{
     collections::{HashMap, HashSet},
+    future::Future,
     io::Write,
-    sync::{Arc, LazyLock, Mutex as StdMutex, Once},
+    path::{Path, PathBuf},
+    pin::Pin,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, LazyLock, Mutex as StdMutex, Once,
+    },
+};
+use tasks::{TaskKind, Tasks};
+use tokio::{
+    runtime::Runtime,
+    sync::{oneshot, Mutex as TokioMutex, Notify, RwLock},
+    task::AbortHandle,
 };
-use tokio::{runtime::Runtime, sync::RwLock};
 use tracing::{error, info, warn};
-use utils::TokioMutexParam;
+use upload::VoiceUploads;
+use utils::MutexParam;
+use vvvst_core::{mixer, wav};
 
 use models::*;
 
-pub static RUNTIME: LazyLock<Runtime> =
-    LazyLock::new(|| Runtime::new().expect("Failed to create runtime"));
+// 全インスタンスで共有する。インスタンスが増えてもスレッドが増えないように、ワーカーの数は固定する。
+pub static RUNTIME: LazyLock<Runtime> = LazyLock::new(|| {
+    tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(worker_threads())
+        .max_blocking_threads(MAX_BLOCKING_THREADS)
+        .thread_keep_alive(BLOCKING_THREAD_KEEP_ALIVE)
+        .thread_name("vvvst-worker")
+        .enable_all()
+        .build()
+        .expect("Failed to create runtime")
+});
 static INITIALIZE_LOG: Once = Once::new();
+static CLEANUP: Once = Once::new();
 
 static EDITOR: Dir = include_dir!("$CARGO_MANIFEST_DIR/editor");
 
+const MAX_WORKER_THREADS: usize = 4;
+// ミックスやファイルの読み書き（spawn_blocking）に使うスレッドの上限。これを超えた分は順番待ちになる。
+// 使われなくなったスレッドはBLOCKING_THREAD_KEEP_ALIVEで終了する
+const MAX_BLOCKING_THREADS: usize = 8;
+const BLOCKING_THREAD_KEEP_ALIVE: std::time::Duration = std::time::Duration::from_secs(10);
+
+// VVVST_WORKER_THREADSで指定できる（ビルド時ではなく実行時の環境変数）
+fn worker_threads() -> usize {
+    let available = std::thread::available_parallelism().map_or(1, |n| n.get());
+    std::env::var("VVVST_WORKER_THREADS")
+        .ok()
+        .and_then(|value| value.parse::<usize>().ok())
+        .filter(|&threads| threads > 0)
+        .unwrap_or_else(|| available.min(MAX_WORKER_THREADS))
+}
+
 // TODO: そのうちマルチトラック・ステレオにする
 #[derive(Debug, Default)]
 struct Mixes {
@@ -30,20 +88,69 @@ struct Mixes {
     sample_rate: f32,
 }
 
+// 処理中のリクエスト。Senderを送るかDropするとそのリクエストはキャンセルされる。
+// キャンセルされたIDがすぐに再利用されても取り違えないように、登録ごとに番号を振る
+type InflightRequests = Arc<StdMutex<HashMap<RequestId, (u64, oneshot::Sender<()>)>>>;
+static NEXT_INFLIGHT: AtomicU64 = AtomicU64::new(0);
+
+// 自動保存のタスク。復元の確認が終わるまではNone。
+type Autosave = Arc<TokioMutex<Option<AbortHandle>>>;
+
+const AUTOSAVE_INTERVAL: std::time::Duration = std::time::Duration::from_secs(30);
+// 状態の復元ではフィールドが一つずつ設定されるので、全て揃うまで待つ時間
+const RESTORE_DEBOUNCE: std::time::Duration = std::time::Duration::from_millis(100);
+// ExportMixで一時フォルダに書き出したファイルを残しておく時間。
+// DAWに読み込まれた後もしばらく参照されることがあるので、書き出してすぐには消さない
+const TEMPORARY_MIX_LIFETIME: std::time::Duration = std::time::Duration::from_secs(24 * 60 * 60);
+// initializeでミックスを待つ時間。ホストのスレッドを止めるので、超えたら残りは非同期で作る
+const INITIAL_MIX_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+
+#[derive(Clone)]
+struct RequestContext {
+    params: Arc<VvvstParams>,
+    mixes: Arc<RwLock<Mixes>>,
+    remix: Arc<RemixScheduler>,
+    uploads: Arc<StdMutex<VoiceUploads>>,
+    inflight: InflightRequests,
+    editor_info: Arc<StdMutex<Option<Handshake>>>,
+    file_access: Arc<StdMutex<FileAccess>>,
+    events: EventSender,
+    autosave: Autosave,
+    tasks: Arc<Tasks>,
+}
+
 struct Vvvst {
     params: Arc<VvvstParams>,
     mixes: Arc<RwLock<Mixes>>,
+    remix: Arc<RemixScheduler>,
+    // ミックスを作り直すタスク。initializeで起動し、deactivateで止める
+    remix_worker: Option<AbortHandle>,
+    uploads: Arc<StdMutex<VoiceUploads>>,
+    inflight: InflightRequests,
+    // Handshakeで受け取ったエディタの情報
+    editor_info: Arc<StdMutex<Option<Handshake>>>,
+    // エディタから読み込めるファイル
+    file_access: Arc<StdMutex<FileAccess>>,
+    autosave: Autosave,
+    // このインスタンスが起動したタスク
+    tasks: Arc<Tasks>,
 
     // 一瞬で終わるのでstdのMutexで十分...のはず？
     response_receiver: Arc<StdMutex<std::sync::mpsc::Receiver<Response>>>,
 
     response_sender: Arc<std::sync::mpsc::Sender<Response>>,
+
+    event_receiver: Arc<StdMutex<std::sync::mpsc::Receiver<Event>>>,
+    event_sender: EventSender,
 }
 
 impl Default for Vvvst {
     fn default() -> Self {
+        let params = Arc::new(VvvstParams::default());
+        let mixes = Arc::new(RwLock::new(Mixes::default()));
+        let remix = Arc::new(RemixScheduler::default());
         INITIALIZE_LOG.call_once(|| {
-            if option_env!("VVVST_LOG").map_or(false, |v| v.len() > 0) {
+            if option_env!("VVVST_LOG").is_some_and(|v| !v.is_empty()) {
                 let dest = std::path::PathBuf::from(env!("CARGO_MANIFEST_DIR").to_string())
                     .join("logs")
                     .join(format!(
@@ -85,7 +192,7 @@ impl Default for Vvvst {
             std::panic::set_hook(Box::new(move |info| {
                 rfd::MessageDialog::new()
                     .set_title("VVVST: Panic")
-                    .set_description(&format!("VVVST Panicked: {:?}", info))
+                    .set_description(format!("VVVST Panicked: {:?}", info))
                     .set_level(rfd::MessageLevel::Error)
                     .set_buttons(rfd::MessageButtons::Ok)
                     .show();
@@ -95,34 +202,263 @@ impl Default for Vvvst {
                 std::process::exit(1);
             }));
         });
+        // 前回までに残ったファイルは、プロセスごとに一度だけ片付ける
+        CLEANUP.call_once(|| {
+            RUNTIME.spawn(Vvvst::remove_old_temporary_mixes());
+            RUNTIME.spawn(async {
+                if let Err(err) = recovery::remove_stale().await {
+                    warn!("failed to remove stale snapshots: {:?}", err);
+                }
+            });
+        });
         let (response_sender, response_receiver) = std::sync::mpsc::channel();
+        let (event_sender, event_receiver) = std::sync::mpsc::channel();
+        let editor_info = Arc::new(StdMutex::new(None));
+        let event_sender = EventSender::new(event_sender, Arc::clone(&editor_info));
+        let tasks = Arc::new(Tasks::default());
+        tasks.spawn(
+            TaskKind::Background,
+            Vvvst::watch_restore(
+                Arc::clone(&params),
+                Arc::clone(&remix),
+                event_sender.clone(),
+            ),
+        );
         Self {
-            params: Arc::new(VvvstParams::default()),
-            mixes: Arc::new(RwLock::new(Mixes::default())),
+            params,
+            mixes,
+            remix,
+            remix_worker: None,
+            uploads: Arc::new(StdMutex::new(VoiceUploads::default())),
+            inflight: Arc::new(StdMutex::new(HashMap::new())),
+            editor_info: Arc::clone(&editor_info),
+            file_access: Arc::new(StdMutex::new(FileAccess::default())),
+            autosave: Arc::new(TokioMutex::new(None)),
+            tasks,
             response_sender: Arc::new(response_sender),
             response_receiver: Arc::new(StdMutex::new(response_receiver)),
+            event_receiver: Arc::new(StdMutex::new(event_receiver)),
+            event_sender,
         }
     }
 }
 
-#[derive(Params, Default)]
+#[derive(Params)]
 struct VvvstParams {
     #[persist = "samples"]
-    voices: TokioMutexParam<HashMap<SingingVoiceKey, Vec<u8>>>,
+    // ミックスのたびに中身をコピーしないように、Arcで共有する
+    voices: MutexParam<HashMap<SingingVoiceKey, Arc<[u8]>>>,
     #[persist = "phrases"]
-    phrases: TokioMutexParam<Vec<Phrase>>,
+    phrases: MutexParam<Vec<Phrase>>,
     #[persist = "project"]
-    project: TokioMutexParam<String>,
+    project: MutexParam<String>,
+    // 自動保存の保存先を決めるためのID。最初に自動保存するときに決める
+    #[persist = "instance-id"]
+    instance_id: MutexParam<String>,
+    // このインスタンスが使っているinstance_id。複製されたトラックでは保存されたものと違うことがある
+    claimed_instance_id: StdMutex<Option<String>>,
+    // プロジェクトかフレーズが最後に変更された時刻（UNIX時間のミリ秒）
+    #[persist = "updated-at"]
+    updated_at: MutexParam<u64>,
+    // エディタを閉じても元に戻せるように、履歴もプラグイン側で持つ
+    #[persist = "history"]
+    history: MutexParam<History>,
+
+    // samples・phrases・projectがホストから復元されたときに通知される
+    restored: Arc<Notify>,
+}
+
+impl Default for VvvstParams {
+    fn default() -> Self {
+        let restored = Arc::new(Notify::new());
+        Self {
+            voices: MutexParam::with_notify(Arc::clone(&restored)),
+            phrases: MutexParam::with_notify(Arc::clone(&restored)),
+            project: MutexParam::with_notify(Arc::clone(&restored)),
+            instance_id: MutexParam::default(),
+            claimed_instance_id: StdMutex::new(None),
+            updated_at: MutexParam::default(),
+            history: MutexParam::default(),
+            restored,
+        }
+    }
+}
+
+impl VvvstParams {
+    fn touch(&self) {
+        *self.updated_at.lock() = recovery::unix_millis();
+    }
+
+    // 自動保存に使うIDを決める。他のインスタンスが同じIDを使っていれば（トラックが複製された場合）作り直す。
+    // 保存されていたIDをそのまま使えたときはtrueを返す
+    fn claim_instance_id(&self) -> (String, bool) {
+        let mut instance_id = self.instance_id.lock();
+        let mut claimed = self.claimed_instance_id.lock().unwrap();
+        if claimed.as_deref() == Some(instance_id.as_str()) {
+            return (instance_id.clone(), true);
+        }
+        // ホストが別の状態を読み込んだ場合は、前のIDを手放す
+        if let Some(previous) = claimed.take() {
+            recovery::release(&previous);
+        }
+        let reused = !instance_id.is_empty() && recovery::claim(&instance_id);
+        if !reused {
+            *instance_id = utils::random_hex();
+            recovery::claim(&instance_id);
+        }
+        *claimed = Some(instance_id.clone());
+        (instance_id.clone(), reused)
+    }
+}
+
+trait FileDialogExt {
+    fn set_directory_opt(self, directory: Option<PathBuf>) -> Self;
+}
+
+impl FileDialogExt for rfd::AsyncFileDialog {
+    fn set_directory_opt(self, directory: Option<PathBuf>) -> Self {
+        match directory {
+            Some(directory) => self.set_directory(directory),
+            None => self,
+        }
+    }
 }
 
 impl Vvvst {
-    async fn process_request(
-        params: Arc<VvvstParams>,
+    fn request_context(&self) -> RequestContext {
+        RequestContext {
+            params: Arc::clone(&self.params),
+            mixes: Arc::clone(&self.mixes),
+            remix: Arc::clone(&self.remix),
+            uploads: Arc::clone(&self.uploads),
+            inflight: Arc::clone(&self.inflight),
+            editor_info: Arc::clone(&self.editor_info),
+            file_access: Arc::clone(&self.file_access),
+            events: self.event_sender.clone(),
+            autosave: Arc::clone(&self.autosave),
+            tasks: Arc::clone(&self.tasks),
+        }
+    }
+
+    // Batchの中から再帰的に呼ぶためのもの
+    fn process_boxed_request(
+        ctx: RequestContext,
         request: RequestInner,
+    ) -> Pin<Box<dyn Future<Output = anyhow::Result<Value>> + Send>> {
+        Box::pin(Vvvst::process_request(ctx, request))
+    }
 
-        mixes: Arc<RwLock<Mixes>>,
-    ) -> anyhow::Result<Value> {
+    fn import_file_dialog(params: &ShowImportFileDialog) -> rfd::AsyncFileDialog {
+        let mut dialog = rfd::AsyncFileDialog::new().set_title(&params.title);
+        if let (Some(name), Some(filters)) = (&params.name, &params.filters) {
+            dialog = dialog.add_filter(name, filters);
+        }
+        for filter in &params.filter_groups {
+            dialog = dialog.add_filter(&filter.name, &filter.extensions);
+        }
+        dialog
+    }
+
+    async fn process_request(ctx: RequestContext, request: RequestInner) -> anyhow::Result<Value> {
+        let RequestContext {
+            params,
+            mixes,
+            remix,
+            uploads,
+            inflight,
+            editor_info,
+            file_access,
+            events,
+            autosave,
+            tasks,
+        } = ctx.clone();
         match request {
+            RequestInner::Cancel { request_id } => {
+                let cancel_sender = inflight.lock().unwrap().remove(&request_id);
+                Ok(serde_json::to_value(
+                    cancel_sender.is_some_and(|(_, sender)| sender.send(()).is_ok()),
+                )?)
+            }
+            RequestInner::Handshake(handshake) => {
+                anyhow::ensure!(
+                    handshake.protocol_version >= MIN_PROTOCOL_VERSION,
+                    IpcError::new(
+                        ErrorCode::UnsupportedProtocol,
+                        format!(
+                            "editor protocol version {} is too old (minimum: {})",
+                            handshake.protocol_version, MIN_PROTOCOL_VERSION
+                        )
+                    )
+                    .with_details(serde_json::json!({
+                        "protocolVersion": PROTOCOL_VERSION,
+                        "minProtocolVersion": MIN_PROTOCOL_VERSION,
+                    }))
+                );
+                let protocol_version = handshake.protocol_version.min(PROTOCOL_VERSION);
+                info!(
+                    "handshake: editor protocol {}, negotiated {}, capabilities: {:?}",
+                    handshake.protocol_version, protocol_version, handshake.capabilities
+                );
+                *editor_info.lock().unwrap() = Some(Handshake {
+                    protocol_version,
+                    ..handshake
+                });
+
+                Ok(serde_json::to_value(HandshakeResult {
+                    protocol_version,
+                    min_protocol_version: MIN_PROTOCOL_VERSION,
+                    plugin_version: env!("CARGO_PKG_VERSION").to_string(),
+                    capabilities: RequestInner::CAPABILITIES
+                        .iter()
+                        .map(|capability| capability.to_string())
+                        .collect(),
+                })?)
+            }
+            RequestInner::Batch(batch) => {
+                let results = if batch.concurrent {
+                    // JoinSetはDropされると中のタスクを中断するので、バッチごとキャンセルできる
+                    let mut tasks = tokio::task::JoinSet::new();
+                    let mut indices = HashMap::new();
+                    for (index, request) in batch.requests.into_iter().enumerate() {
+                        let ctx = ctx.clone();
+                        let handle = tasks.spawn(Vvvst::process_boxed_request(ctx, request));
+                        indices.insert(handle.id(), index);
+                    }
+                    let mut results = (0..tasks.len()).map(|_| None).collect::<Vec<_>>();
+                    while let Some(result) = tasks.join_next_with_id().await {
+                        // パニックしたリクエストがあっても、他の結果はそのまま返す
+                        let (id, result) = match result {
+                            Ok((id, result)) => (id, result),
+                            Err(err) => (
+                                err.id(),
+                                Err(IpcError::new(
+                                    ErrorCode::Internal,
+                                    format!("request task failed: {}", err),
+                                )
+                                .into()),
+                            ),
+                        };
+                        results[indices[&id]] = Some(result);
+                    }
+                    results
+                        .into_iter()
+                        .map(|result| result.expect("every task should be joined"))
+                        .collect()
+                } else {
+                    let mut results = Vec::with_capacity(batch.requests.len());
+                    for request in batch.requests {
+                        results.push(Vvvst::process_boxed_request(ctx.clone(), request).await);
+                    }
+                    results
+                };
+
+                Ok(serde_json::to_value(BatchResult(
+                    results
+                        .into_iter()
+                        .map(|result| result.map_err(IpcError::from))
+                        .collect(),
+                ))?)
+            }
             RequestInner::GetVersion => Ok(serde_json::to_value(env!("CARGO_PKG_VERSION"))?),
             RequestInner::GetProjectName => Ok(serde_json::to_value("VVVST")?),
             RequestInner::GetConfig => {
@@ -149,94 +485,389 @@ impl Vvvst {
                 Ok(serde_json::to_value(config)?)
             }
             RequestInner::GetProject => {
-                let project = params.project.lock().await.clone();
+                Vvvst::start_autosave(&params, &remix, &autosave, &tasks).await;
+                let project = params.project.lock().clone();
                 Ok(serde_json::to_value(project)?)
             }
             RequestInner::SetProject(project) => {
-                let mut project_ref = params.project.lock().await;
+                project::validate(&project)?;
+                let mut project_ref = params.project.lock();
+                params.history.lock().record(&project_ref, &project);
                 *project_ref = project;
+                params.touch();
                 Ok(serde_json::Value::Null)
             }
-            RequestInner::SetPhrases(phrases) => {
-                let mut phrases_ref = params.phrases.lock().await;
-                *phrases_ref = phrases;
-
-                let mut samples = params.voices.lock().await.clone();
-                let missing_voices = phrases_ref
-                    .iter()
-                    .filter_map(|phrase| {
-                        if samples.contains_key(&phrase.voice) {
-                            None
-                        } else {
-                            Some(phrase.voice.clone())
-                        }
-                    })
-                    .collect::<HashSet<_>>();
-                let unused_voices = samples
-                    .keys()
-                    .filter(|voice| !phrases_ref.iter().any(|phrase| phrase.voice == **voice))
-                    .cloned()
-                    .collect::<HashSet<_>>();
-                for audio_hash in unused_voices {
-                    samples.remove(&audio_hash);
+            RequestInner::Undo | RequestInner::Redo => {
+                let mut project = params.project.lock();
+                let mut history = params.history.lock();
+                let restored = if matches!(request, RequestInner::Undo) {
+                    history.undo(&project)
+                } else {
+                    history.redo(&project)
+                };
+                if let Some(restored) = &restored {
+                    *project = restored.clone();
+                    params.touch();
                 }
+                Ok(serde_json::to_value(restored)?)
+            }
+            RequestInner::GetHistory => Ok(serde_json::to_value(params.history.lock().status())?),
+            RequestInner::SetPhrases(phrases) => {
+                let missing_voices = {
+                    let voices = params.voices.lock();
+                    phrases
+                        .iter()
+                        .filter(|phrase| !voices.contains_key(&phrase.voice))
+                        .map(|phrase| phrase.voice.clone())
+                        .collect::<HashSet<_>>()
+                };
+                *params.phrases.lock() = phrases;
+                params.touch();
+
                 Ok(serde_json::to_value(SetPhraseResult {
                     missing_voices: missing_voices.into_iter().collect(),
                 })?)
             }
             RequestInner::SetVoices(samples) => {
+                // ホストの保存を待たせないように、デコードはロックの外でする
+                let samples = samples
+                    .into_iter()
+                    .map(|(audio_hash, sample)| Ok((audio_hash, base64.decode(sample)?.into())))
+                    .collect::<anyhow::Result<Vec<(_, Arc<[u8]>)>>>()?;
                 {
-                    let mut samples_ref = params.voices.lock().await;
+                    let mut uploads = uploads.lock().unwrap();
+                    let mut samples_ref = params.voices.lock();
                     for (audio_hash, sample) in samples {
-                        samples_ref.insert(audio_hash, base64.decode(sample)?);
+                        // まとめて送られてきた音声で、受信途中のチャンクは置き換える
+                        uploads.remove(&audio_hash);
+                        samples_ref.insert(audio_hash, sample);
                     }
                 }
 
-                let params = Arc::clone(&params);
-                let mixes = Arc::clone(&mixes);
-
-                tokio::spawn(async move {
-                    Vvvst::update_mixes(params, mixes, None).await;
-                });
+                remix.request(None);
                 Ok(serde_json::Value::Null)
             }
-            RequestInner::ShowImportFileDialog(params) => {
-                let dialog = match &params {
-                    ShowImportFileDialog {
-                        title,
-                        name: Some(name),
-                        filters: Some(filters),
-                    } => rfd::AsyncFileDialog::new()
-                        .set_title(title)
-                        .add_filter(name, filters),
-                    ShowImportFileDialog { title, .. } => {
-                        rfd::AsyncFileDialog::new().set_title(title)
+            RequestInner::SetVoiceChunk(chunk) => {
+                let data = base64.decode(&chunk.data)?;
+                let (status, voice) = uploads.lock().unwrap().receive(&chunk, data)?;
+
+                if let Some(voice) = voice {
+                    // フレーズから使われている音声が揃ったときだけミックスし直す
+                    let used = params
+                        .phrases
+                        .lock()
+                        .iter()
+                        .any(|phrase| phrase.voice == chunk.voice);
+                    params.voices.lock().insert(chunk.voice, voice.into());
+                    if used {
+                        remix.request(None);
                     }
+                }
+                Ok(serde_json::to_value(status)?)
+            }
+            RequestInner::GetVoiceUploadStatus(voice) => {
+                let status = uploads.lock().unwrap().status(&voice);
+                let status = match status {
+                    Some(status) => Some(status),
+                    None if params.voices.lock().contains_key(&voice) => Some(VoiceUploadStatus {
+                        total_chunks: 0,
+                        missing_chunks: vec![],
+                        complete: true,
+                    }),
+                    None => None,
                 };
+                Ok(serde_json::to_value(status)?)
+            }
+            RequestInner::ShowImportFileDialog(payload) => {
+                let purpose = payload.purpose.or(Some(DialogPurpose::Import));
+                let dialog = Vvvst::import_file_dialog(&payload).set_directory_opt(
+                    settings::dialog_directory(purpose, &payload.starting_directory),
+                );
 
                 let result = dialog.pick_file().await;
-                return Ok(serde_json::to_value(
-                    result.map(|path| path.path().to_string_lossy().to_string()),
-                )?);
+                if let Some(path) = &result {
+                    settings::remember_file_directory(purpose, path.path());
+                }
+                let picked =
+                    result.map(|path| file_access.lock().unwrap().grant(path.path(), Access::Read));
+                if protocol_version(&editor_info) < PICKED_FILE_PROTOCOL_VERSION {
+                    return Ok(serde_json::to_value(picked.map(|picked| picked.path))?);
+                }
+                Ok(serde_json::to_value(picked)?)
             }
-            RequestInner::ReadFile(path) => {
+            RequestInner::ShowImportFilesDialog(payload) => {
+                let purpose = payload.purpose.or(Some(DialogPurpose::Import));
+                let dialog = Vvvst::import_file_dialog(&payload).set_directory_opt(
+                    settings::dialog_directory(purpose, &payload.starting_directory),
+                );
+
+                let result = dialog.pick_files().await;
+                if let Some(path) = result.as_ref().and_then(|paths| paths.first()) {
+                    settings::remember_file_directory(purpose, path.path());
+                }
+                Ok(serde_json::to_value(result.map(|paths| {
+                    let mut file_access = file_access.lock().unwrap();
+                    paths
+                        .iter()
+                        .map(|path| file_access.grant(path.path(), Access::Read))
+                        .collect::<Vec<_>>()
+                }))?)
+            }
+            RequestInner::ShowFolderDialog(payload) => {
+                let dialog = rfd::AsyncFileDialog::new()
+                    .set_title(&payload.title)
+                    .set_can_create_directories(true)
+                    .set_directory_opt(settings::dialog_directory(
+                        payload.purpose,
+                        &payload.starting_directory,
+                    ));
+
+                let result = dialog.pick_folder().await;
+                if let Some(path) = &result {
+                    settings::remember_directory(payload.purpose, path.path());
+                }
+                // 書き出し先として選ばれたフォルダだけ書き込みを許可する
+                let access = match payload.purpose {
+                    Some(DialogPurpose::ProjectExport | DialogPurpose::AudioExport) => {
+                        Access::Write
+                    }
+                    Some(DialogPurpose::Import) | None => Access::Read,
+                };
+                Ok(serde_json::to_value(result.map(|path| {
+                    file_access
+                        .lock()
+                        .unwrap()
+                        .grant_directory(path.path(), access)
+                }))?)
+            }
+            RequestInner::ReadFile(file) => {
+                let path = file_access.lock().unwrap().resolve(&file, Access::Read)?;
                 let content = tokio::fs::read(path).await?;
                 let encoded = base64.encode(&content);
                 Ok(serde_json::to_value(encoded)?)
             }
-            RequestInner::ExportProject => {
+            RequestInner::ShowSaveFileDialog(payload) => {
+                let mut dialog = rfd::AsyncFileDialog::new().set_title(&payload.title);
+                if let Some(default_name) = &payload.default_name {
+                    dialog = dialog.set_file_name(default_name);
+                }
+                for filter in &payload.filters {
+                    dialog = dialog.add_filter(&filter.name, &filter.extensions);
+                }
+                let dialog = dialog.set_directory_opt(settings::dialog_directory(
+                    payload.purpose,
+                    &payload.starting_directory,
+                ));
+
+                let result = dialog.save_file().await;
+                if let Some(path) = &result {
+                    settings::remember_file_directory(payload.purpose, path.path());
+                }
+                Ok(serde_json::to_value(result.map(|path| {
+                    file_access
+                        .lock()
+                        .unwrap()
+                        .grant(path.path(), Access::Write)
+                }))?)
+            }
+            RequestInner::WriteFile(params) => {
+                let path = file_access
+                    .lock()
+                    .unwrap()
+                    .resolve(&params.file, Access::Write)?;
+                let content = base64.decode(&params.data)?;
+                tokio::fs::write(path, content).await?;
+                Ok(serde_json::Value::Null)
+            }
+            RequestInner::ExportProject(options) => {
+                let options = options.unwrap_or_default();
+                let project = {
+                    let project = params.project.lock();
+                    anyhow::ensure!(
+                        !project.is_empty(),
+                        IpcError::new(ErrorCode::NotFound, "no project to export")
+                    );
+                    if options.pretty {
+                        serde_json::to_string_pretty(&project::validate(&project)?)?
+                    } else {
+                        project::validate(&project)?;
+                        project.clone()
+                    }
+                };
+
+                let purpose = Some(DialogPurpose::ProjectExport);
                 let destination = rfd::AsyncFileDialog::new()
                     .set_title("プロジェクトファイルの書き出し")
                     .add_filter("VOICEVOX Project File", &["vvproj"])
+                    .set_directory_opt(settings::dialog_directory(purpose, &None))
                     .save_file()
                     .await;
                 if let Some(destination) = destination {
-                    let project = params.project.lock().await.clone();
+                    settings::remember_file_directory(purpose, destination.path());
                     tokio::fs::write(destination.path(), project).await?;
-                    return Ok(serde_json::Value::Bool(true));
+                    Ok(serde_json::Value::Bool(true))
                 } else {
+                    Ok(serde_json::Value::Bool(false))
+                }
+            }
+            RequestInner::ImportProject => {
+                // 書き出しと同じフォルダを使う
+                let purpose = Some(DialogPurpose::ProjectExport);
+                let source = rfd::AsyncFileDialog::new()
+                    .set_title("プロジェクトファイルの読み込み")
+                    .add_filter("VOICEVOX Project File", &["vvproj"])
+                    .set_directory_opt(settings::dialog_directory(purpose, &None))
+                    .pick_file()
+                    .await;
+                let Some(source) = source else {
                     return Ok(serde_json::Value::Bool(false));
+                };
+                settings::remember_file_directory(purpose, source.path());
+
+                let project = tokio::fs::read_to_string(source.path()).await?;
+                project::validate(&project)?;
+                {
+                    let mut project_ref = params.project.lock();
+                    params.history.lock().record(&project_ref, &project);
+                    *project_ref = project;
                 }
+                params.touch();
+                events.send(Event::ProjectLoaded);
+                Ok(serde_json::Value::Bool(true))
+            }
+            // NOTE: WebViewからはOSのドラッグを始められないので、ファイルに書き出してパスを返すだけにしている
+            RequestInner::ExportMix(payload) => {
+                let wav = {
+                    let mixes = mixes.read().await;
+                    anyhow::ensure!(
+                        mixes.sample_rate > 0.0,
+                        IpcError::new(ErrorCode::Busy, "mix is not ready yet")
+                    );
+                    let to_sample = |seconds: f32| {
+                        ((seconds * mixes.sample_rate).max(0.0) as usize).min(mixes.mixes.len())
+                    };
+                    let start = payload.start.map_or(0, to_sample);
+                    let end = payload.end.map_or(mixes.mixes.len(), to_sample).max(start);
+                    wav::encode(
+                        &mixes.mixes[start..end],
+                        mixes.sample_rate as u32,
+                        1,
+                        BitDepth::Float32,
+                    )
+                };
+
+                let destination = match payload.destination {
+                    MixDestination::Temporary => {
+                        let directory = temporary_mix_directory();
+                        tokio::fs::create_dir_all(&directory).await?;
+                        // 同じミリ秒に書き出されても（バッチや複数のインスタンス）重ならないようにする
+                        Some(directory.join(format!(
+                            "vvvst-{}-{}.wav",
+                            recovery::unix_millis(),
+                            utils::random_hex()
+                        )))
+                    }
+                    MixDestination::Dialog => {
+                        let purpose = Some(DialogPurpose::AudioExport);
+                        let destination = rfd::AsyncFileDialog::new()
+                            .set_title("音声の書き出し")
+                            .add_filter("WAV", &["wav"])
+                            .set_file_name("vvvst.wav")
+                            .set_directory_opt(settings::dialog_directory(purpose, &None))
+                            .save_file()
+                            .await;
+                        if let Some(destination) = &destination {
+                            settings::remember_file_directory(purpose, destination.path());
+                        }
+                        destination.map(|destination| destination.path().to_path_buf())
+                    }
+                };
+                let Some(destination) = destination else {
+                    return Ok(serde_json::Value::Null);
+                };
+
+                tokio::fs::write(&destination, wav).await?;
+                Ok(serde_json::to_value(
+                    file_access
+                        .lock()
+                        .unwrap()
+                        .grant(&destination, Access::Read),
+                )?)
+            }
+            RequestInner::RenderAudio(payload) => {
+                anyhow::ensure!(
+                    (1..=2).contains(&payload.channels),
+                    IpcError::new(ErrorCode::InvalidArgument, "channels must be 1 or 2")
+                );
+                anyhow::ensure!(
+                    (8000..=192000).contains(&payload.sample_rate),
+                    IpcError::new(
+                        ErrorCode::InvalidArgument,
+                        "sampleRate must be between 8000 and 192000"
+                    )
+                );
+                let (filter_name, extension) = match payload.format {
+                    AudioFormat::Wav => ("WAV", "wav"),
+                    AudioFormat::Flac => ("FLAC", "flac"),
+                };
+                let flac_bits = match (payload.format, payload.bit_depth) {
+                    (AudioFormat::Flac, BitDepth::Int16) => 16,
+                    (AudioFormat::Flac, BitDepth::Int24) => 24,
+                    (AudioFormat::Flac, BitDepth::Float32) => anyhow::bail!(IpcError::new(
+                        ErrorCode::InvalidArgument,
+                        "FLAC does not support 32-bit float samples"
+                    )),
+                    (AudioFormat::Wav, _) => 0,
+                };
+
+                let purpose = Some(DialogPurpose::AudioExport);
+                let destination = rfd::AsyncFileDialog::new()
+                    .set_title("音声の書き出し")
+                    .add_filter(filter_name, &[extension])
+                    .set_file_name(format!("vvvst.{}", extension))
+                    .set_directory_opt(settings::dialog_directory(purpose, &None))
+                    .save_file()
+                    .await;
+                let Some(destination) = destination else {
+                    return Ok(serde_json::Value::Null);
+                };
+                settings::remember_file_directory(purpose, destination.path());
+
+                let phrases = params.phrases.lock().clone();
+                let voices = params.voices.lock().clone();
+                let sample_rate = payload.sample_rate;
+                let channels = payload.channels;
+                let bit_depth = payload.bit_depth;
+                let format = payload.format;
+                let encoded = tokio::task::spawn_blocking(move || {
+                    let mix = mixer::mix(&phrases, &voices, sample_rate as f32)?;
+                    // 一部の音声が抜けたまま書き出さないように、作り直してもらう
+                    anyhow::ensure!(
+                        mix.invalid_voices.is_empty(),
+                        IpcError::new(ErrorCode::InvalidArgument, "some voices are not valid wav")
+                            .with_details(serde_json::json!({
+                                "invalidVoices": mix.invalid_voices,
+                            }))
+                    );
+                    let samples = mixer::to_channels(mix.samples, channels);
+                    match format {
+                        AudioFormat::Wav => {
+                            Ok(wav::encode(&samples, sample_rate, channels, bit_depth))
+                        }
+                        AudioFormat::Flac => {
+                            flac::encode(&samples, sample_rate, channels, flac_bits)
+                        }
+                    }
+                })
+                .await??;
+
+                tokio::fs::write(destination.path(), encoded).await?;
+                Ok(serde_json::to_value(
+                    file_access
+                        .lock()
+                        .unwrap()
+                        .grant(destination.path(), Access::Read),
+                )?)
             }
             RequestInner::ShowMessageDialog(params) => {
                 let dialog = rfd::AsyncMessageDialog::new()
@@ -251,110 +882,217 @@ impl Vvvst {
                 };
                 dialog.show().await;
 
-                return Ok(serde_json::Value::Null);
+                Ok(serde_json::Value::Null)
             }
             RequestInner::ShowQuestionDialog(params) => {
-                anyhow::ensure!(
-                    (1..=3).contains(&params.buttons.len()),
-                    "The number of buttons must be 1 to 3"
-                );
-                let dialog = rfd::AsyncMessageDialog::new()
-                    .set_title(&params.title)
-                    .set_description(&params.message);
-                let dialog = match params.r#type {
-                    DialogType::Info => dialog.set_level(rfd::MessageLevel::Info),
-                    DialogType::Warning => dialog.set_level(rfd::MessageLevel::Warning),
-                    DialogType::Error => dialog.set_level(rfd::MessageLevel::Error),
-                    _ => dialog,
-                };
-                let dialog = dialog.set_buttons(match params.buttons.len() {
-                    1 => rfd::MessageButtons::OkCustom(params.buttons[0].clone()),
-                    2 => rfd::MessageButtons::OkCancelCustom(
-                        params.buttons[0].clone(),
-                        params.buttons[1].clone(),
-                    ),
-                    3 => rfd::MessageButtons::YesNoCancelCustom(
-                        params.buttons[0].clone(),
-                        params.buttons[1].clone(),
-                        params.buttons[2].clone(),
-                    ),
-                    _ => unreachable!(),
-                });
-                let result = dialog.show().await;
-                let rfd::MessageDialogResult::Custom(custom_text) = result else {
-                    anyhow::bail!("Unexpected dialog result: {:?}", result);
-                };
-                return Ok(serde_json::to_value(
-                    params
-                        .buttons
-                        .iter()
-                        .position(|button| button == &custom_text),
-                )?);
+                let button = dialog::show_question_dialog(&params).await?;
+                Ok(serde_json::to_value(button)?)
             }
         }
     }
 
-    async fn update_mixes(
-        params: Arc<VvvstParams>,
-        mixes: Arc<RwLock<Mixes>>,
-        new_sample_rate: Option<f32>,
+    // 最初にエディタがプロジェクトを読み込むときに、保存されなかった変更があれば復元するかを確認する。
+    // 確認より前に自動保存するとスナップショットが上書きされてしまうので、自動保存はその後に始める。
+    async fn start_autosave(
+        params: &Arc<VvvstParams>,
+        remix: &RemixScheduler,
+        autosave: &Autosave,
+        tasks: &Tasks,
     ) {
-        let phrases = params.phrases.lock().await.clone();
-        let voices = params.voices.lock().await.clone();
-        let mut mixes = mixes.write().await;
-        mixes.mixes.clear();
-        info!("updating mixes using {} phrases", phrases.len());
-
-        let new_sample_rate = new_sample_rate.unwrap_or(mixes.sample_rate);
-
-        let max_start = phrases
-            .iter()
-            .map(|phrase| phrase.start)
-            .fold(0.0, f32::max);
-        let mut mix = vec![0.0; (max_start * new_sample_rate) as usize];
-        for phrase in phrases {
-            if let Some(voice) = voices.get(&phrase.voice) {
-                let mut wav = wav_io::reader::Reader::from_vec(voice.clone()).unwrap();
-                let header = wav.read_header().unwrap();
-                let base_samples = wav.get_samples_f32().unwrap();
-                let samples = if header.channels == 1 {
-                    base_samples
-                } else {
-                    wav_io::utils::stereo_to_mono(base_samples)
-                };
-                let samples = wav_io::resample::linear(
-                    samples,
-                    1,
-                    header.sample_rate,
-                    (new_sample_rate) as u32,
-                );
-                let start = (phrase.start * new_sample_rate).floor() as isize;
-                let end = start + samples.len() as isize;
+        let mut autosave = autosave.lock().await;
+        if autosave.is_some() {
+            return;
+        }
+        match Vvvst::recover(params).await {
+            Ok(true) => {
+                remix.request(None);
+            }
+            Ok(false) => {}
+            Err(err) => warn!("failed to recover from snapshot: {:?}", err),
+        }
+        *autosave = Some(tasks.spawn(TaskKind::Background, Vvvst::autosave(Arc::clone(params))));
+    }
 
-                if end > mix.len() as isize {
-                    mix.resize(end as usize, 0.0);
-                }
-                for i in 0..samples.len() {
-                    let frame = start + i as isize;
-                    if frame < 0 {
-                        continue;
-                    }
-                    let frame = frame as usize;
-                    if mix[frame] > f32::MAX - samples[i] {
-                        mix[frame] = f32::MAX;
-                    } else if mix[frame] < f32::MIN - samples[i] {
-                        mix[frame] = f32::MIN;
-                    } else {
-                        mix[frame] += samples[i];
-                    }
+    async fn recover(params: &VvvstParams) -> anyhow::Result<bool> {
+        let (instance_id, reused) = params.claim_instance_id();
+        if !reused {
+            return Ok(false);
+        }
+        let Some(snapshot) = recovery::load(&instance_id).await? else {
+            return Ok(false);
+        };
+        if snapshot.updated_at <= *params.updated_at.lock() {
+            return Ok(false);
+        }
+
+        let answer = dialog::show_question_dialog(&ShowQuestionDialog {
+            r#type: DialogType::Question,
+            title: "VVVST".to_string(),
+            message: "保存されていない変更が見つかりました。復元しますか？".to_string(),
+            buttons: vec!["復元する".to_string(), "破棄する".to_string()],
+            cancel_id: Some(1),
+            default_id: Some(0),
+        })
+        .await?;
+        if answer != 0 {
+            recovery::remove(&instance_id);
+            return Ok(false);
+        }
+        info!("recovering from snapshot at {}", snapshot.updated_at);
+        // 履歴は復元前のプロジェクトに対するものなので使えない
+        params.history.lock().clear();
+        *params.project.lock() = snapshot.project;
+        *params.phrases.lock() = snapshot.phrases;
+        *params.updated_at.lock() = snapshot.updated_at;
+        Ok(true)
+    }
+
+    async fn autosave(params: Arc<VvvstParams>) {
+        let mut saved_at = *params.updated_at.lock();
+        let mut interval = tokio::time::interval(AUTOSAVE_INTERVAL);
+        loop {
+            interval.tick().await;
+            let updated_at = *params.updated_at.lock();
+            if updated_at <= saved_at {
+                continue;
+            }
+
+            let phrases = params.phrases.lock().clone();
+            let snapshot = recovery::Snapshot {
+                updated_at,
+                project: params.project.lock().clone(),
+                voices: phrases.iter().map(|phrase| phrase.voice.clone()).collect(),
+                phrases,
+            };
+            let (instance_id, _) = params.claim_instance_id();
+            match recovery::save(&instance_id, &snapshot).await {
+                Ok(()) => saved_at = updated_at,
+                Err(err) => warn!("failed to save snapshot: {:?}", err),
+            }
+        }
+    }
+
+    async fn remove_old_temporary_mixes() {
+        let result: anyhow::Result<()> = async {
+            let mut entries = match tokio::fs::read_dir(temporary_mix_directory()).await {
+                Ok(entries) => entries,
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+                Err(err) => return Err(err.into()),
+            };
+            while let Some(entry) = entries.next_entry().await? {
+                let path = entry.path();
+                let is_mix = path
+                    .file_name()
+                    .and_then(|name| name.to_str())
+                    .is_some_and(|name| name.starts_with("vvvst-") && name.ends_with(".wav"));
+                let expired = entry
+                    .metadata()
+                    .await?
+                    .modified()?
+                    .elapsed()
+                    .is_ok_and(|age| age > TEMPORARY_MIX_LIFETIME);
+                if is_mix && expired {
+                    tokio::fs::remove_file(&path).await?;
                 }
             }
+            Ok(())
+        }
+        .await;
+        if let Err(err) = result {
+            warn!("failed to remove old temporary mixes: {:?}", err);
         }
+    }
+
+    // ホストが状態を復元したら、ミックスを作り直してエディタに読み込み直してもらう
+    async fn watch_restore(
+        params: Arc<VvvstParams>,
+        remix: Arc<RemixScheduler>,
+        events: EventSender,
+    ) {
+        loop {
+            params.restored.notified().await;
+            while tokio::time::timeout(RESTORE_DEBOUNCE, params.restored.notified())
+                .await
+                .is_ok()
+            {}
+            info!("state restored by host");
+
+            remix.request(None);
+            events.send(Event::StateReloaded);
+        }
+    }
+
+    // 要求された最新の状態だけをミックスする。ミックスはロックの外で行い、
+    // その間に新しい要求が来ていたら結果を捨てて作り直す。
+    async fn remix_worker(
+        remix: Arc<RemixScheduler>,
+        params: Arc<VvvstParams>,
+        mixes: Arc<RwLock<Mixes>>,
+    ) {
+        Self::remix_worker_with(remix, params, mixes, mixer::mix).await
+    }
 
-        info!("mixes updated, {} samples", mix.len());
+    // テストでミックスを差し替えられるように分けている
+    async fn remix_worker_with(
+        remix: Arc<RemixScheduler>,
+        params: Arc<VvvstParams>,
+        mixes: Arc<RwLock<Mixes>>,
+        mix: impl Fn(&[Phrase], &HashMap<SingingVoiceKey, Arc<[u8]>>, f32) -> anyhow::Result<mixer::Mix>
+            + Send
+            + Sync
+            + 'static,
+    ) {
+        let mix = Arc::new(mix);
+        let mut rendered = 0;
+        loop {
+            remix.wait_for_request(rendered).await;
+            let generation = remix.generation();
+            rendered = generation;
+            let sample_rate = remix.sample_rate();
+            // サンプルレートが決まっていなければ、initializeかprocess()で決まったときにミックスされる
+            if sample_rate <= 0.0 {
+                remix.mark_rendered(generation);
+                continue;
+            }
 
-        mixes.mixes = mix;
-        mixes.sample_rate = new_sample_rate;
+            let phrases = params.phrases.lock().clone();
+            let voices = params.voices.lock().clone();
+            info!(
+                "updating mixes using {} phrases (generation {})",
+                phrases.len(),
+                generation
+            );
+            let mix = match tokio::task::spawn_blocking({
+                let mix = Arc::clone(&mix);
+                move || mix(&phrases, &voices, sample_rate)
+            })
+            .await
+            .map_err(anyhow::Error::from)
+            .and_then(|mix| mix)
+            {
+                Ok(mix) => mix,
+                Err(err) => {
+                    // 待っているinitializeが止まらないように、終わったことにする
+                    error!("failed to update mixes: {:?}", err);
+                    remix.mark_rendered(generation);
+                    continue;
+                }
+            };
+            if !remix.is_latest(generation) {
+                info!("dropping stale mixes (generation {})", generation);
+                continue;
+            }
+
+            if !mix.invalid_voices.is_empty() {
+                warn!("skipped invalid voices: {:?}", mix.invalid_voices);
+            }
+            info!("mixes updated, {} samples", mix.samples.len());
+            let mut mixes = mixes.write().await;
+            mixes.mixes = mix.samples;
+            mixes.sample_rate = sample_rate;
+            drop(mixes);
+            remix.mark_rendered(generation);
+        }
     }
 }
 
@@ -382,6 +1120,45 @@ impl Plugin for Vvvst {
         self.params.clone()
     }
 
+    // 最初のprocess()が無音にならないように、ここでミックスを済ませておく
+    fn initialize(
+        &mut self,
+        _audio_io_layout: &AudioIOLayout,
+        buffer_config: &BufferConfig,
+        _context: &mut impl InitContext<Self>,
+    ) -> bool {
+        info!("initializing: sample rate {}", buffer_config.sample_rate);
+        if self
+            .remix_worker
+            .as_ref()
+            .is_none_or(AbortHandle::is_finished)
+        {
+            self.remix_worker = Some(self.tasks.spawn(
+                TaskKind::Remix,
+                Vvvst::remix_worker(
+                    Arc::clone(&self.remix),
+                    Arc::clone(&self.params),
+                    Arc::clone(&self.mixes),
+                ),
+            ));
+        }
+        let rendered = RUNTIME.block_on(async {
+            tokio::time::timeout(
+                INITIAL_MIX_TIMEOUT,
+                self.remix.render(buffer_config.sample_rate),
+            )
+            .await
+        });
+        if rendered.is_err() {
+            // 待つのをやめるだけで、ミックスはそのまま続く。できるまでは前のミックスか無音になる
+            warn!(
+                "mixing took longer than {:?}, continuing in the background",
+                INITIAL_MIX_TIMEOUT
+            );
+        }
+        true
+    }
+
     fn process(
         &mut self,
         buffer: &mut Buffer,
@@ -391,7 +1168,7 @@ impl Plugin for Vvvst {
         let transport = context.transport();
         if let Ok(mixes) = self.mixes.try_read() {
             if transport.sample_rate == mixes.sample_rate {
-                if transport.playing && mixes.mixes.len() > 0 {
+                if transport.playing && !mixes.mixes.is_empty() {
                     let current_sample = transport.pos_samples().unwrap() as usize;
                     let samples_len = buffer.samples();
                     let sample_range =
@@ -411,12 +1188,10 @@ impl Plugin for Vvvst {
                         }
                     }
                 }
-            } else {
-                RUNTIME.spawn(Vvvst::update_mixes(
-                    Arc::clone(&self.params),
-                    Arc::clone(&self.mixes),
-                    Some(transport.sample_rate),
-                ));
+            } else if self.remix.sample_rate() != transport.sample_rate {
+                // 同じサンプルレートでは一度だけ要求する。タスクの起動はしないが、
+                // Notifyの中で短いロックを取るので、サンプルレートが変わったときだけにする
+                self.remix.request(Some(transport.sample_rate));
             }
         }
 
@@ -424,10 +1199,18 @@ impl Plugin for Vvvst {
     }
 
     fn editor(&mut self, _async_executor: AsyncExecutor<Self>) -> Option<Box<dyn Editor>> {
-        let params = Arc::clone(&self.params);
+        let request_context = self.request_context();
         let response_sender = self.response_sender.clone();
         let response_receiver = self.response_receiver.clone();
-        let mixes = Arc::clone(&self.mixes);
+        let event_receiver = self.event_receiver.clone();
+        let event_sender = self.event_sender.clone();
+        let file_access = Arc::clone(&self.file_access);
+        let uploads = Arc::clone(&self.uploads);
+        let inflight = Arc::clone(&self.inflight);
+        let tasks = Arc::clone(&self.tasks);
+        let pending_responses = self.response_receiver.clone();
+        let pending_events = self.event_receiver.clone();
+        let editor_info = Arc::clone(&self.editor_info);
 
         let editor = WebViewEditor::new(
             HTMLSource::URL(if cfg!(debug_assertions) {
@@ -464,32 +1247,33 @@ impl Plugin for Vvvst {
         })
         .with_background_color((165, 212, 173, 255))
         .with_developer_mode(cfg!(debug_assertions))
-        .with_keyboard_handler(move |event| {
-            println!("keyboard event: {event:#?}");
-            event.key == Key::Escape
-        })
-        .with_mouse_handler(|event| match event {
-            MouseEvent::DragEntered { .. } => {
-                println!("drag entered");
-                EventStatus::AcceptDrop(DropEffect::Copy)
-            }
-            MouseEvent::DragMoved { .. } => {
-                println!("drag moved");
+        .with_keyboard_handler(move |event| event.key == Key::Escape)
+        .with_mouse_handler(move |event| match event {
+            MouseEvent::DragEntered { .. } | MouseEvent::DragMoved { .. } => {
                 EventStatus::AcceptDrop(DropEffect::Copy)
             }
-            MouseEvent::DragLeft => {
-                println!("drag left");
-                EventStatus::Ignored
-            }
+            MouseEvent::DragLeft => EventStatus::Ignored,
             MouseEvent::DragDropped { data, .. } => {
                 if let DropData::Files(files) = data {
-                    println!("drag dropped: {:?}", files);
+                    info!("drag dropped: {:?}", files);
+                    // ドロップされたファイルは、ダイアログで選ばれたのと同じく読み込めるようにする
+                    let files = {
+                        let mut file_access = file_access.lock().unwrap();
+                        files
+                            .iter()
+                            .filter(|file| is_droppable_file(file))
+                            .map(|file| file_access.grant(file, Access::Read))
+                            .collect::<Vec<_>>()
+                    };
+                    if !files.is_empty() {
+                        event_sender.send(Event::FilesDropped(files));
+                    }
                 }
                 EventStatus::AcceptDrop(DropEffect::Copy)
             }
             _ => EventStatus::Ignored,
         })
-        .with_event_loop(move |ctx, setter, window| {
+        .with_event_loop(move |ctx, _setter, _window| {
             while let Ok(value) = ctx.next_event() {
                 let value = match serde_json::from_value::<Request>(value.clone()) {
                     Ok(value) => value,
@@ -497,9 +1281,25 @@ impl Plugin for Vvvst {
                         // 可能な限りエラーを返してあげる
                         let request_id = value["requestId"].as_u64();
                         if let Some(request_id) = request_id {
+                            // 知らないリクエストは、エディタ側で判別できるように別のエラーにする
+                            let error = match value["inner"]["type"].as_str() {
+                                Some(request_type) if !RequestInner::is_supported(request_type) => {
+                                    IpcError::new(
+                                        ErrorCode::UnsupportedRequest,
+                                        format!("unsupported request type: {}", request_type),
+                                    )
+                                    .with_details(serde_json::json!({
+                                        "requestType": request_type,
+                                    }))
+                                }
+                                _ => IpcError::new(
+                                    ErrorCode::ParseError,
+                                    format!("failed to parse request: {}", err),
+                                ),
+                            };
                             let response = Response {
                                 request_id: RequestId(request_id as u32),
-                                payload: Err(format!("failed to parse request: {}", err)),
+                                payload: Err(error),
                             };
                             warn!("failed to parse request: {}", err);
                             response_sender.send(response).unwrap();
@@ -509,33 +1309,128 @@ impl Plugin for Vvvst {
                         continue;
                     }
                 };
-                let params = Arc::clone(&params);
+                let request_context = request_context.clone();
                 let response_sender = Arc::clone(&response_sender);
-                let mixes = Arc::clone(&mixes);
 
-                RUNTIME.spawn(async move {
-                    let result = Vvvst::process_request(params, value.inner, mixes).await;
+                // タスクが終わる前に登録されるように、ロックを持ったままspawnする
+                let inflight = Arc::clone(&request_context.inflight);
+                let mut inflight_requests = inflight.lock().unwrap();
+                // 同じIDのリクエストが処理中だと、キャンセルや応答の相手が分からなくなる
+                if inflight_requests.contains_key(&value.request_id) {
+                    warn!("duplicate request id: {:?}", value.request_id);
+                    let _ = response_sender.send(Response {
+                        request_id: value.request_id,
+                        payload: Err(IpcError::new(
+                            ErrorCode::InvalidArgument,
+                            format!("request id {} is already in use", value.request_id.0),
+                        )),
+                    });
+                    continue;
+                }
+                let (cancel_sender, cancel_receiver) = oneshot::channel();
+                let inflight_id = NEXT_INFLIGHT.fetch_add(1, Ordering::Relaxed);
+                inflight_requests.insert(value.request_id, (inflight_id, cancel_sender));
+
+                let tasks = Arc::clone(&request_context.tasks);
+                tasks.spawn(TaskKind::Request, async move {
+                    let inflight = Arc::clone(&request_context.inflight);
+                    let result = tokio::select! {
+                        result = Vvvst::process_request(request_context, value.inner) => result,
+                        _ = cancel_receiver => {
+                            Err(IpcError::new(ErrorCode::Cancelled, "request cancelled").into())
+                        }
+                    };
+                    // キャンセルされた後に同じIDで来た別のリクエストは消さない
+                    let mut inflight_requests = inflight.lock().unwrap();
+                    if inflight_requests
+                        .get(&value.request_id)
+                        .is_some_and(|(id, _)| *id == inflight_id)
+                    {
+                        inflight_requests.remove(&value.request_id);
+                    }
+                    drop(inflight_requests);
+
                     let response = Response {
                         request_id: value.request_id,
                         payload: match result {
                             Ok(value) => Ok(value),
-                            Err(err) => Err(err.to_string()),
+                            Err(err) => Err(IpcError::from(err)),
                         },
                     };
-                    response_sender.send(response).unwrap();
+                    // プラグインが破棄された後は受け取り手がいないことがある
+                    let _ = response_sender.send(response);
                 });
+                drop(inflight_requests);
             }
 
+            let protocol_version = protocol_version(&request_context.editor_info);
             while let Ok(response) = response_receiver.lock().unwrap().try_recv() {
-                ctx.send_json(serde_json::to_value(response).unwrap())
-                    .unwrap();
+                ctx.send_json(response.to_json(protocol_version)).unwrap();
+            }
+            while let Ok(event) = event_receiver.lock().unwrap().try_recv() {
+                ctx.send_json(serde_json::to_value(event).unwrap()).unwrap();
             }
         });
 
-        Some(Box::new(editor))
+        Some(Box::new(ClosableEditor::new(editor, move || {
+            // 次に開いたエディタは最初から送り直すので、受信途中のチャンクは捨てる
+            uploads.lock().unwrap().clear();
+            // 処理中のリクエストは応答を受け取る相手がいないので止める
+            for (_, (_, cancel_sender)) in inflight.lock().unwrap().drain() {
+                let _ = cancel_sender.send(());
+            }
+            tasks.abort(TaskKind::Request);
+            while pending_responses.lock().unwrap().try_recv().is_ok() {}
+            // 次のエディタがHandshakeするまではイベントを溜めない
+            *editor_info.lock().unwrap() = None;
+            while pending_events.lock().unwrap().try_recv().is_ok() {}
+        })))
+    }
+
+    // 途中のミックスは捨てられるが、世代は残るので次のinitializeで最新の状態が作り直される
+    fn deactivate(&mut self) {
+        self.tasks.abort(TaskKind::Remix);
+        self.remix_worker = None;
     }
+}
+
+// Handshakeで交渉したバージョン。Handshakeをしないエディタは一番古いものとして扱う
+fn protocol_version(editor_info: &StdMutex<Option<Handshake>>) -> u32 {
+    editor_info
+        .lock()
+        .unwrap()
+        .as_ref()
+        .map_or(MIN_PROTOCOL_VERSION, |handshake| handshake.protocol_version)
+}
+
+// ExportMixで一時的に書き出すファイルの置き場所
+fn temporary_mix_directory() -> PathBuf {
+    std::env::temp_dir().join("vvvst")
+}
+
+// エディタが読み込めるファイル
+fn is_droppable_file(path: &Path) -> bool {
+    const EXTENSIONS: &[&str] = &[
+        "mid", "midi", "musicxml", "xml", "mxl", "ust", "vvproj", "wav",
+    ];
+    path.extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| EXTENSIONS.contains(&extension.to_lowercase().as_str()))
+}
+
+impl Drop for Vvvst {
+    fn drop(&mut self) {
+        // 処理中のリクエストは全てキャンセルする
+        self.inflight.lock().unwrap().clear();
+        // リクエストのタスクもTasksを持っているので、Dropを待たずに止める
+        self.tasks.abort_all();
 
-    fn deactivate(&mut self) {}
+        // 正常に終了したときは復元する必要がないので、スナップショットを消す
+        if let Some(instance_id) = self.params.claimed_instance_id.lock().unwrap().take() {
+            recovery::remove(&instance_id);
+            recovery::release(&instance_id);
+        }
+    }
 }
 
 impl Vst3Plugin for Vvvst {
@@ -557,3 +1452,73 @@ impl ClapPlugin for Vvvst {
 
 nih_export_vst3!(Vvvst);
 nih_export_clap!(Vvvst);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nih_plug::params::persist::PersistentField;
+    use std::sync::mpsc;
+    use std::time::Duration;
+
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    #[test]
+    fn save_during_long_mix() {
+        let params = Arc::new(VvvstParams::default());
+        let remix = Arc::new(RemixScheduler::default());
+        let mixes = Arc::new(RwLock::new(Mixes::default()));
+        let phrases = (0..1000)
+            .map(|i| Phrase {
+                start: i as f32,
+                voice: SingingVoiceKey(format!("voice-{}", i)),
+            })
+            .collect::<Vec<_>>();
+        params.phrases.set(phrases);
+
+        // ミックスが始まったら知らせて、releaseされるまで終わらないミックス
+        let (started_sender, started_receiver) = mpsc::channel();
+        let (release_sender, release_receiver) = mpsc::channel::<()>();
+        let release_receiver = StdMutex::new(release_receiver);
+        let worker = RUNTIME.spawn(Vvvst::remix_worker_with(
+            Arc::clone(&remix),
+            Arc::clone(&params),
+            Arc::clone(&mixes),
+            move |phrases, _voices, _sample_rate| {
+                started_sender.send(phrases.len()).unwrap();
+                release_receiver.lock().unwrap().recv().unwrap();
+                Ok(mixer::Mix {
+                    samples: vec![0.5; phrases.len()],
+                    invalid_voices: vec![],
+                })
+            },
+        ));
+        let generation = remix.request(Some(48000.0));
+        assert_eq!(started_receiver.recv_timeout(TIMEOUT).unwrap(), 1000);
+
+        // ミックスが終わっていなくても、ホストの保存と読み込みは終わる
+        let (saved_sender, saved_receiver) = mpsc::channel();
+        std::thread::spawn({
+            let params = Arc::clone(&params);
+            move || {
+                let saved = params
+                    .phrases
+                    .map(|phrases| serde_json::to_string(phrases).unwrap());
+                params.phrases.set(vec![]);
+                saved_sender.send(saved).unwrap();
+            }
+        });
+        let saved = saved_receiver.recv_timeout(TIMEOUT).unwrap();
+        assert!(saved.contains("voice-999"));
+        assert!(RUNTIME.block_on(mixes.read()).mixes.is_empty());
+
+        release_sender.send(()).unwrap();
+        RUNTIME.block_on(async {
+            tokio::time::timeout(TIMEOUT, remix.wait_for_render(generation))
+                .await
+                .unwrap()
+        });
+        assert_eq!(RUNTIME.block_on(mixes.read()).mixes.len(), 1000);
+        assert!(params.phrases.lock().is_empty());
+        worker.abort();
+    }
+}
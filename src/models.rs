@@ -1,30 +1,60 @@
+use crate::error::IpcError;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use std::collections::HashMap;
+use ts_rs::TS;
+// CLIと共有する型
+pub use vvvst_core::models::{BitDepth, Phrase, SingingVoiceKey};
 
-#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
-pub struct SingingVoiceKey(pub String);
-
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, TS)]
 pub struct RequestId(pub u32);
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+// エディタとの通信プロトコルのバージョン。互換性のない変更をしたら上げる。
+// 古いエディタにはそのバージョンの形で応答するので、MIN_PROTOCOL_VERSIONは対応している一番古いものにしておく。
+pub const PROTOCOL_VERSION: u32 = 3;
+pub const MIN_PROTOCOL_VERSION: u32 = 1;
+// エラーをIpcErrorで返すようになったバージョン。それより前は文字列
+pub const IPC_ERROR_PROTOCOL_VERSION: u32 = 2;
+// ダイアログで選ばれたファイルをPickedFileで返すようになったバージョン。それより前はパスの文字列
+pub const PICKED_FILE_PROTOCOL_VERSION: u32 = 3;
+
+#[derive(Debug, Clone, Serialize, Deserialize, TS)]
 #[serde(rename_all = "camelCase")]
 pub struct Response {
     pub request_id: RequestId,
-    pub payload: Result<Value, String>,
+    pub payload: Result<Value, IpcError>,
+}
+
+impl Response {
+    /// 交渉したバージョンのエディタが読める形にする。
+    pub fn to_json(&self, protocol_version: u32) -> Value {
+        match &self.payload {
+            Err(error) if protocol_version < IPC_ERROR_PROTOCOL_VERSION => serde_json::json!({
+                "requestId": self.request_id,
+                "payload": { "Err": error.message },
+            }),
+            _ => serde_json::to_value(self).expect("response should be serializable"),
+        }
+    }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, TS)]
 #[serde(rename_all = "camelCase")]
 pub struct Request {
     pub request_id: RequestId,
     pub inner: RequestInner,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, TS)]
 #[serde(rename_all = "camelCase", tag = "type", content = "payload")]
 pub enum RequestInner {
+    #[serde(rename_all = "camelCase")]
+    Cancel {
+        request_id: RequestId,
+    },
+
+    Handshake(Handshake),
+    Batch(BatchRequest),
     GetVersion,
     GetProjectName,
 
@@ -33,40 +63,293 @@ pub enum RequestInner {
     SetProject(String),
     SetPhrases(Vec<Phrase>),
     SetVoices(HashMap<SingingVoiceKey, String>),
+    SetVoiceChunk(VoiceChunk),
+    GetVoiceUploadStatus(SingingVoiceKey),
 
     ShowMessageDialog(ShowMessageDialog),
     ShowImportFileDialog(ShowImportFileDialog),
+    ShowImportFilesDialog(ShowImportFileDialog),
+    ShowFolderDialog(ShowFolderDialog),
+    ShowSaveFileDialog(ShowSaveFileDialog),
     ShowQuestionDialog(ShowQuestionDialog),
 
-    ReadFile(String),
+    ReadFile(FileRef),
+    WriteFile(WriteFile),
+
+    ExportProject(Option<ExportProjectOptions>),
+    ImportProject,
+    Undo,
+    Redo,
+    GetHistory,
+    ExportMix(ExportMix),
+    RenderAudio(RenderAudio),
+}
+
+impl RequestInner {
+    // RequestInnerにリクエストを追加したらここにも追加すること。
+    pub const CAPABILITIES: &'static [&'static str] = &[
+        "cancel",
+        "handshake",
+        "batch",
+        "getVersion",
+        "getProjectName",
+        "getConfig",
+        "getProject",
+        "setProject",
+        "setPhrases",
+        "setVoices",
+        "setVoiceChunk",
+        "getVoiceUploadStatus",
+        "showMessageDialog",
+        "showImportFileDialog",
+        "showImportFilesDialog",
+        "showFolderDialog",
+        "showSaveFileDialog",
+        "showQuestionDialog",
+        "readFile",
+        "writeFile",
+        "exportProject",
+        "importProject",
+        "undo",
+        "redo",
+        "getHistory",
+        "exportMix",
+        "renderAudio",
+    ];
+
+    pub fn is_supported(request_type: &str) -> bool {
+        Self::CAPABILITIES.contains(&request_type)
+    }
+}
 
-    ExportProject,
+// プラグインからエディタへの通知。requestIdを持たないことでResponseと区別する。
+#[derive(Debug, Clone, Serialize, Deserialize, TS)]
+#[serde(rename_all = "camelCase", tag = "type", content = "payload")]
+pub enum Event {
+    FilesDropped(Vec<PickedFile>),
+    // プロジェクトが差し替えられたので、エディタはGetProjectで読み込み直す
+    ProjectLoaded,
+    // ホストから状態が復元されたので、エディタは全て読み込み直す
+    StateReloaded,
+}
+
+impl Event {
+    // Handshakeのcapabilitiesで使う名前
+    pub fn event_type(&self) -> &'static str {
+        match self {
+            Event::FilesDropped(_) => "filesDropped",
+            Event::ProjectLoaded => "projectLoaded",
+            Event::StateReloaded => "stateReloaded",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, TS)]
+#[serde(rename_all = "camelCase")]
+pub struct Handshake {
+    pub protocol_version: u32,
+    // エディタが受け取れるイベントの種類
+    #[serde(default)]
+    #[ts(as = "Option<Vec<String>>", optional)]
+    pub capabilities: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, TS)]
+#[serde(rename_all = "camelCase")]
+pub struct HandshakeResult {
+    pub protocol_version: u32,
+    pub min_protocol_version: u32,
+    pub plugin_version: String,
+    pub capabilities: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, TS)]
+#[serde(rename_all = "camelCase")]
+pub struct BatchRequest {
+    pub requests: Vec<RequestInner>,
+    // trueなら全てのリクエストを同時に処理する。falseなら順番に処理する。
+    #[serde(default)]
+    #[ts(as = "Option<bool>", optional)]
+    pub concurrent: bool,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, TS)]
+pub struct BatchResult(pub Vec<Result<Value, IpcError>>);
+
+#[derive(Debug, Clone, Serialize, Deserialize, TS)]
 #[serde(rename_all = "camelCase")]
 pub struct ShowImportFileDialog {
     pub title: String,
     #[serde(default)]
+    #[ts(optional = nullable)]
     pub name: Option<String>,
     #[serde(default)]
+    #[ts(optional = nullable)]
     pub filters: Option<Vec<String>>,
+    // name/filtersより後に追加される
+    #[serde(default)]
+    #[ts(as = "Option<Vec<FileFilter>>", optional)]
+    pub filter_groups: Vec<FileFilter>,
+    #[serde(default)]
+    #[ts(optional = nullable)]
+    pub starting_directory: Option<String>,
+    #[serde(default)]
+    #[ts(optional = nullable)]
+    pub purpose: Option<DialogPurpose>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+// ダイアログの用途。用途ごとに前回使ったフォルダを覚えておく（settings.rs）
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, TS)]
 #[serde(rename_all = "camelCase")]
-pub struct Phrase {
-    pub start: f32,
-    pub voice: SingingVoiceKey,
+pub enum DialogPurpose {
+    Import,
+    ProjectExport,
+    AudioExport,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, TS)]
+#[serde(rename_all = "camelCase")]
+pub struct ShowFolderDialog {
+    pub title: String,
+    #[serde(default)]
+    #[ts(optional = nullable)]
+    pub starting_directory: Option<String>,
+    #[serde(default)]
+    #[ts(optional = nullable)]
+    pub purpose: Option<DialogPurpose>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, TS)]
+#[serde(rename_all = "camelCase")]
+pub struct FileFilter {
+    pub name: String,
+    pub extensions: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, TS)]
+#[serde(rename_all = "camelCase")]
+pub struct ShowSaveFileDialog {
+    pub title: String,
+    #[serde(default)]
+    #[ts(optional = nullable)]
+    pub default_name: Option<String>,
+    #[serde(default)]
+    #[ts(as = "Option<Vec<FileFilter>>", optional)]
+    pub filters: Vec<FileFilter>,
+    #[serde(default)]
+    #[ts(optional = nullable)]
+    pub starting_directory: Option<String>,
+    #[serde(default)]
+    #[ts(optional = nullable)]
+    pub purpose: Option<DialogPurpose>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, TS)]
+#[serde(rename_all = "camelCase")]
+pub struct WriteFile {
+    pub file: FileRef,
+    // Base64でエンコードされた内容
+    pub data: String,
+}
+
+// ダイアログで選ばれたファイル。tokenをReadFile（保存ダイアログならWriteFile）に渡すと読み書きできる。
+#[derive(Debug, Clone, Serialize, Deserialize, TS)]
+#[serde(rename_all = "camelCase")]
+pub struct PickedFile {
+    pub path: String,
+    pub token: String,
+}
+
+// トークンか、このセッションでユーザーが選んだファイルのパス。
+// フォルダのトークンの場合は、nameでフォルダ直下のファイルを指定する。
+#[derive(Debug, Clone, Serialize, Deserialize, TS)]
+#[serde(untagged)]
+pub enum FileRef {
+    // untaggedなので、フィールドの多いものから並べる
+    InDirectory { token: String, name: String },
+    Token { token: String },
+    Path(String),
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, TS)]
+#[serde(rename_all = "camelCase")]
+pub struct ExportProjectOptions {
+    // gitで差分を見やすいように、インデントを付けて書き出す
+    #[serde(default)]
+    #[ts(as = "Option<bool>", optional)]
+    pub pretty: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, TS)]
+#[serde(rename_all = "camelCase")]
+pub struct HistoryStatus {
+    pub undo_count: usize,
+    pub redo_count: usize,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, TS)]
+#[serde(rename_all = "camelCase")]
+pub struct ExportMix {
+    // 秒単位の範囲。省略すると最初・最後まで
+    #[serde(default)]
+    #[ts(optional = nullable)]
+    pub start: Option<f32>,
+    #[serde(default)]
+    #[ts(optional = nullable)]
+    pub end: Option<f32>,
+    pub destination: MixDestination,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, TS)]
+#[serde(rename_all = "camelCase")]
+pub enum MixDestination {
+    // 一時フォルダに書き出す。DAWへのドラッグ&ドロップやエクスプローラーでの表示に使う
+    Temporary,
+    // 保存ダイアログで選んだファイルに書き出す
+    Dialog,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, TS)]
+#[serde(rename_all = "camelCase")]
+pub struct RenderAudio {
+    pub format: AudioFormat,
+    pub sample_rate: u32,
+    pub bit_depth: BitDepth,
+    // 1ならモノラル、2ならステレオ
+    pub channels: u16,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, TS)]
+#[serde(rename_all = "camelCase")]
+pub enum AudioFormat {
+    Wav,
+    Flac,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, TS)]
 #[serde(rename_all = "camelCase")]
 pub struct SetPhraseResult {
     pub missing_voices: Vec<SingingVoiceKey>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, TS)]
+#[serde(rename_all = "camelCase")]
+pub struct VoiceChunk {
+    pub voice: SingingVoiceKey,
+    pub index: usize,
+    pub total_chunks: usize,
+    pub data: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, TS)]
+#[serde(rename_all = "camelCase")]
+pub struct VoiceUploadStatus {
+    pub total_chunks: usize,
+    pub missing_chunks: Vec<usize>,
+    pub complete: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, TS)]
 #[serde(rename_all = "camelCase")]
 pub struct ShowMessageDialog {
     pub r#type: DialogType,
@@ -74,7 +357,7 @@ pub struct ShowMessageDialog {
     pub message: String,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, TS)]
 #[serde(rename_all = "camelCase")]
 pub enum DialogType {
     None,
@@ -84,7 +367,7 @@ pub enum DialogType {
     Question,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, TS)]
 #[serde(rename_all = "camelCase")]
 pub struct ShowQuestionDialog {
     pub r#type: DialogType,
@@ -92,7 +375,111 @@ pub struct ShowQuestionDialog {
     pub message: String,
     pub buttons: Vec<String>,
     #[serde(default)]
+    #[ts(optional = nullable)]
     pub cancel_id: Option<usize>,
     #[serde(default)]
+    #[ts(optional = nullable)]
     pub default_id: Option<usize>,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::ErrorCode;
+    use std::path::Path;
+
+    fn typescript_bindings() -> String {
+        let declarations = [
+            Value::decl(),
+            SingingVoiceKey::decl(),
+            RequestId::decl(),
+            Response::decl(),
+            IpcError::decl(),
+            ErrorCode::decl(),
+            Request::decl(),
+            RequestInner::decl(),
+            Event::decl(),
+            Handshake::decl(),
+            HandshakeResult::decl(),
+            BatchRequest::decl(),
+            BatchResult::decl(),
+            ShowImportFileDialog::decl(),
+            DialogPurpose::decl(),
+            ShowFolderDialog::decl(),
+            FileFilter::decl(),
+            ShowSaveFileDialog::decl(),
+            WriteFile::decl(),
+            PickedFile::decl(),
+            ExportMix::decl(),
+            ExportProjectOptions::decl(),
+            HistoryStatus::decl(),
+            MixDestination::decl(),
+            RenderAudio::decl(),
+            AudioFormat::decl(),
+            BitDepth::decl(),
+            FileRef::decl(),
+            Phrase::decl(),
+            SetPhraseResult::decl(),
+            VoiceChunk::decl(),
+            VoiceUploadStatus::decl(),
+            ShowMessageDialog::decl(),
+            DialogType::decl(),
+            ShowQuestionDialog::decl(),
+        ];
+
+        let mut bindings = "// このファイルは自動生成されています。`src/models.rs`を編集し、\n\
+             // `VVVST_UPDATE_BINDINGS=1 cargo test`で再生成してください。\n"
+            .to_string();
+        for declaration in declarations {
+            bindings.push_str(&format!("\nexport {}\n", declaration));
+        }
+        bindings
+    }
+
+    #[test]
+    fn typescript_bindings_are_up_to_date() {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("bindings/models.ts");
+        let bindings = typescript_bindings();
+        if std::env::var("VVVST_UPDATE_BINDINGS").is_ok_and(|v| !v.is_empty()) {
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(&path, bindings).unwrap();
+            return;
+        }
+
+        let committed = std::fs::read_to_string(&path)
+            .unwrap_or_default()
+            .replace("\r\n", "\n");
+        assert!(
+            committed == bindings,
+            "bindings/models.ts is outdated; run `VVVST_UPDATE_BINDINGS=1 cargo test` to regenerate it"
+        );
+    }
+
+    #[test]
+    fn capabilities_cover_all_requests() {
+        let declaration = RequestInner::decl();
+        let request_types = declaration
+            .split("\"type\": \"")
+            .skip(1)
+            .map(|part| part.split('"').next().unwrap())
+            .collect::<Vec<_>>();
+
+        assert_eq!(request_types, RequestInner::CAPABILITIES);
+    }
+
+    #[test]
+    fn errors_follow_negotiated_version() {
+        let response = Response {
+            request_id: RequestId(1),
+            payload: Err(IpcError::new(ErrorCode::NotFound, "not found")),
+        };
+        assert_eq!(
+            response.to_json(MIN_PROTOCOL_VERSION),
+            serde_json::json!({ "requestId": 1, "payload": { "Err": "not found" } })
+        );
+        assert_eq!(
+            response.to_json(PROTOCOL_VERSION)["payload"]["Err"]["code"],
+            "notFound"
+        );
+    }
+}
@@ -0,0 +1,86 @@
+use crate::error::{ErrorCode, IpcError};
+use serde_json::Value;
+
+/// VOICEVOXのプロジェクトファイルとして読めるかを確認する。
+///
+/// 中身の細かい検証はエディタ側のマイグレーションに任せ、ここではトップレベルの形と
+/// appVersionだけを見る。
+pub fn validate(project: &str) -> Result<Value, IpcError> {
+    let value: Value = serde_json::from_str(project).map_err(|err| {
+        invalid(format!(
+            "project is not valid JSON (line {}, column {})",
+            err.line(),
+            err.column()
+        ))
+    })?;
+    let Some(root) = value.as_object() else {
+        return Err(invalid("project must be a JSON object"));
+    };
+
+    match root.get("appVersion") {
+        Some(Value::String(version)) if is_version(version) => {}
+        Some(Value::String(version)) => {
+            return Err(invalid(format!("appVersion is not a version: {}", version)))
+        }
+        Some(_) => return Err(invalid("appVersion must be a string")),
+        None => return Err(invalid("appVersion is missing")),
+    }
+
+    for key in ["talk", "song"] {
+        if root.get(key).is_some_and(|value| !value.is_object()) {
+            return Err(invalid(format!("{} must be an object", key)));
+        }
+    }
+    // 0.14より前のプロジェクトはtalkを持たず、audioItemsがトップレベルにある
+    let has_content = ["talk", "song", "audioItems"]
+        .iter()
+        .any(|key| root.contains_key(*key));
+    if !has_content {
+        return Err(invalid("project has neither talk nor song"));
+    }
+
+    Ok(value)
+}
+
+fn invalid(message: impl Into<String>) -> IpcError {
+    IpcError::new(ErrorCode::InvalidArgument, message)
+}
+
+// "0.21.1"や"999.999.999-dev"のような形式
+fn is_version(version: &str) -> bool {
+    let core = version.split(['-', '+']).next().unwrap_or_default();
+    let parts = core.split('.').collect::<Vec<_>>();
+    parts.len() == 3
+        && parts
+            .iter()
+            .all(|part| !part.is_empty() && part.chars().all(|c| c.is_ascii_digit()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn valid_projects() {
+        assert!(validate(r#"{"appVersion":"0.21.1","talk":{},"song":{}}"#).is_ok());
+        assert!(validate(r#"{"appVersion":"999.999.999-dev","song":{}}"#).is_ok());
+        // 0.14より前の形式
+        assert!(validate(r#"{"appVersion":"0.13.0","audioItems":{}}"#).is_ok());
+    }
+
+    #[test]
+    fn invalid_projects() {
+        for project in [
+            "",
+            "[]",
+            r#"{"talk":{}}"#,
+            r#"{"appVersion":1,"talk":{}}"#,
+            r#"{"appVersion":"latest","talk":{}}"#,
+            r#"{"appVersion":"0.21.1","talk":[]}"#,
+            r#"{"appVersion":"0.21.1"}"#,
+        ] {
+            let err = validate(project).unwrap_err();
+            assert_eq!(err.code, ErrorCode::InvalidArgument, "{}", project);
+        }
+    }
+}
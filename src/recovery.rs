@@ -0,0 +1,121 @@
+use crate::models::{Phrase, SingingVoiceKey};
+use crate::utils::data_directory;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::path::PathBuf;
+use std::sync::{LazyLock, Mutex};
+use std::time::Duration;
+
+// クラッシュに備えて、DAWの保存とは別にプロジェクトを書き出しておく。
+// 音声は大きすぎるので保存せず、フレーズが参照するキーだけを残す（復元後にエディタが作り直す）。
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Snapshot {
+    pub updated_at: u64,
+    pub project: String,
+    pub phrases: Vec<Phrase>,
+    pub voices: Vec<SingingVoiceKey>,
+}
+
+const SNAPSHOT_FILE: &str = "snapshot.json";
+// これより長く更新されていないスナップショットは、DAWのプロジェクトごと消されたものとして消す
+const STALE_SNAPSHOT_AGE: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+
+// このプロセスで使われているID。トラックを複製するとDAWの状態ごとIDもコピーされるので、
+// 同じIDを使うインスタンスが二つ以上できないようにする。
+static LIVE_INSTANCES: LazyLock<Mutex<HashSet<String>>> =
+    LazyLock::new(|| Mutex::new(HashSet::new()));
+
+/// IDを使い始める。他のインスタンスが使っている場合はfalse。
+pub fn claim(instance_id: &str) -> bool {
+    LIVE_INSTANCES
+        .lock()
+        .unwrap()
+        .insert(instance_id.to_string())
+}
+
+pub fn release(instance_id: &str) {
+    LIVE_INSTANCES.lock().unwrap().remove(instance_id);
+}
+
+/// インスタンスごとの復元用フォルダ。
+pub fn directory(instance_id: &str) -> PathBuf {
+    data_directory()
+        .join("vvvst")
+        .join("recovery")
+        .join(instance_id)
+}
+
+pub async fn save(instance_id: &str, snapshot: &Snapshot) -> anyhow::Result<()> {
+    let directory = directory(instance_id);
+    tokio::fs::create_dir_all(&directory).await?;
+    // 書き込み中に落ちても前のスナップショットが壊れないように、別名で書いてから置き換える
+    let temporary = directory.join(format!("{}.tmp", SNAPSHOT_FILE));
+    tokio::fs::write(&temporary, serde_json::to_vec(snapshot)?).await?;
+    tokio::fs::rename(&temporary, directory.join(SNAPSHOT_FILE)).await?;
+    Ok(())
+}
+
+pub async fn load(instance_id: &str) -> anyhow::Result<Option<Snapshot>> {
+    match tokio::fs::read(directory(instance_id).join(SNAPSHOT_FILE)).await {
+        Ok(data) => Ok(Some(serde_json::from_slice(&data)?)),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err.into()),
+    }
+}
+
+// Dropから呼ぶので同期的に消す
+pub fn remove(instance_id: &str) {
+    let _ = std::fs::remove_dir_all(directory(instance_id));
+}
+
+/// 長く更新されていないスナップショットを消す。
+pub async fn remove_stale() -> anyhow::Result<()> {
+    let mut entries =
+        match tokio::fs::read_dir(data_directory().join("vvvst").join("recovery")).await {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+            Err(err) => return Err(err.into()),
+        };
+    while let Some(entry) = entries.next_entry().await? {
+        let Some(instance_id) = entry.file_name().to_str().map(str::to_string) else {
+            continue;
+        };
+        if LIVE_INSTANCES.lock().unwrap().contains(&instance_id) {
+            continue;
+        }
+        // スナップショットがないフォルダは、書き込み途中で落ちたものなのでフォルダの時刻で判断する
+        let modified = match tokio::fs::metadata(entry.path().join(SNAPSHOT_FILE)).await {
+            Ok(metadata) => metadata.modified()?,
+            Err(_) => entry.metadata().await?.modified()?,
+        };
+        if modified.elapsed().is_ok_and(|age| age > STALE_SNAPSHOT_AGE) {
+            tokio::fs::remove_dir_all(entry.path()).await?;
+        }
+    }
+    Ok(())
+}
+
+pub fn unix_millis() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn duplicated_ids_cannot_be_claimed() {
+        let instance_id = crate::utils::random_hex();
+
+        assert!(claim(&instance_id));
+        // 複製されたトラックは同じIDを持っている
+        assert!(!claim(&instance_id));
+        release(&instance_id);
+        assert!(claim(&instance_id));
+        release(&instance_id);
+    }
+}
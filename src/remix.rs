@@ -0,0 +1,128 @@
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use tokio::sync::{watch, Notify};
+
+// ミックスの作り直しの要求をまとめる。
+// 要求のたびに世代を進め、ミックスするタスクは最新の世代だけを作る。ミックス中に新しい要求が来たら、
+// その結果は古いので捨てる。
+#[derive(Debug)]
+pub struct RemixScheduler {
+    generation: AtomicU64,
+    // f32のビット列。0.0ならまだサンプルレートが決まっていない
+    sample_rate: AtomicU32,
+    notify: Notify,
+    rendered: watch::Sender<u64>,
+}
+
+impl Default for RemixScheduler {
+    fn default() -> Self {
+        Self {
+            generation: AtomicU64::new(0),
+            sample_rate: AtomicU32::new(0.0f32.to_bits()),
+            notify: Notify::new(),
+            rendered: watch::Sender::new(0),
+        }
+    }
+}
+
+impl RemixScheduler {
+    /// ミックスを作り直すように要求する。サンプルレートを指定しなかった場合は前回のものを使う。
+    pub fn request(&self, sample_rate: Option<f32>) -> u64 {
+        if let Some(sample_rate) = sample_rate {
+            self.sample_rate
+                .store(sample_rate.to_bits(), Ordering::Release);
+        }
+        let generation = self.generation.fetch_add(1, Ordering::AcqRel) + 1;
+        self.notify.notify_one();
+        generation
+    }
+
+    /// 要求してから、その世代（かそれより新しいもの）のミックスができるまで待つ。
+    pub async fn render(&self, sample_rate: f32) {
+        let generation = self.request(Some(sample_rate));
+        self.wait_for_render(generation).await;
+    }
+
+    /// その世代（かそれより新しいもの）のミックスができるまで待つ。
+    pub async fn wait_for_render(&self, generation: u64) {
+        let mut rendered = self.rendered.subscribe();
+        let _ = rendered.wait_for(|&rendered| rendered >= generation).await;
+    }
+
+    pub fn sample_rate(&self) -> f32 {
+        f32::from_bits(self.sample_rate.load(Ordering::Acquire))
+    }
+
+    pub fn generation(&self) -> u64 {
+        self.generation.load(Ordering::Acquire)
+    }
+
+    pub fn is_latest(&self, generation: u64) -> bool {
+        self.generation() == generation
+    }
+
+    /// 新しい要求が来るまで待つ。
+    pub async fn wait_for_request(&self, rendered: u64) {
+        while self.generation() == rendered {
+            self.notify.notified().await;
+        }
+    }
+
+    pub fn mark_rendered(&self, generation: u64) {
+        self.rendered.send_if_modified(|rendered| {
+            let modified = *rendered < generation;
+            *rendered = (*rendered).max(generation);
+            modified
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn burst_coalesces_into_one_render() {
+        let remix = RemixScheduler::default();
+        crate::RUNTIME.block_on(async {
+            let first = remix.request(Some(44100.0));
+            for _ in 0..8 {
+                remix.request(None);
+            }
+            let latest = remix.request(Some(48000.0));
+
+            // ワーカーは起きた時点の最新の世代を一度だけ作る
+            remix.wait_for_request(0).await;
+            let generation = remix.generation();
+            assert_eq!(generation, latest);
+            assert_eq!(remix.sample_rate(), 48000.0);
+            assert!(remix.is_latest(generation));
+            remix.mark_rendered(generation);
+
+            // 途中の世代を待っていたものも、最新のミックスで終わる
+            remix.wait_for_render(first).await;
+            // 次の要求が来るまでは作り直さない
+            assert!(tokio::time::timeout(
+                Duration::from_millis(50),
+                remix.wait_for_request(generation)
+            )
+            .await
+            .is_err());
+        });
+    }
+
+    #[test]
+    fn request_during_render_makes_it_stale() {
+        let remix = RemixScheduler::default();
+        crate::RUNTIME.block_on(async {
+            let generation = remix.request(Some(48000.0));
+            remix.wait_for_request(0).await;
+            let newer = remix.request(None);
+            assert!(!remix.is_latest(generation));
+
+            remix.wait_for_request(generation).await;
+            assert_eq!(remix.generation(), newer);
+            assert_eq!(remix.sample_rate(), 48000.0);
+        });
+    }
+}
@@ -0,0 +1,322 @@
+use crate::error::{ErrorCode, IpcError};
+use crate::models::{FileRef, PickedFile};
+use crate::utils::random_hex;
+use std::collections::HashMap;
+use std::path::{Component, Path, PathBuf};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    Write,
+}
+
+#[derive(Debug)]
+enum Grant {
+    File { path: PathBuf, access: Access },
+    // フォルダの中のファイル。シンボリックリンクでフォルダの外を指すものは含まない
+    Directory { path: PathBuf, access: Access },
+}
+
+// エディタから触れるファイルの一覧。ユーザーがダイアログで選んだファイルだけを登録し、
+// それ以外のパスへのアクセスは拒否する。
+#[derive(Debug, Default)]
+pub struct FileAccess {
+    grants: HashMap<String, Grant>,
+}
+
+impl FileAccess {
+    /// ファイルへのアクセスを許可する。
+    pub fn grant(&mut self, path: &Path, access: Access) -> PickedFile {
+        self.insert(
+            path,
+            Grant::File {
+                path: normalize(path),
+                access,
+            },
+        )
+    }
+
+    /// フォルダ直下のファイルへのアクセスを許可する。
+    pub fn grant_directory(&mut self, path: &Path, access: Access) -> PickedFile {
+        self.insert(
+            path,
+            Grant::Directory {
+                path: normalize(path),
+                access,
+            },
+        )
+    }
+
+    fn insert(&mut self, path: &Path, grant: Grant) -> PickedFile {
+        let token = random_hex();
+        self.grants.insert(token.clone(), grant);
+        PickedFile {
+            path: path.to_string_lossy().to_string(),
+            token,
+        }
+    }
+
+    pub fn resolve(&self, file: &FileRef, access: Access) -> Result<PathBuf, IpcError> {
+        let path = match file {
+            FileRef::InDirectory { token, name } => match self.grants.get(token) {
+                Some(Grant::Directory {
+                    path,
+                    access: granted,
+                }) if *granted == access => in_directory(path, name)?,
+                _ => None,
+            },
+            FileRef::Token { token } => match self.grants.get(token) {
+                Some(Grant::File {
+                    path,
+                    access: granted,
+                }) if *granted == access => Some(path.clone()),
+                _ => None,
+            },
+            FileRef::Path(path) => {
+                let requested = Path::new(path);
+                let normalized = normalize(requested);
+                self.grants.values().find_map(|grant| match grant {
+                    Grant::File {
+                        path: granted,
+                        access: granted_access,
+                    } if *granted_access == access && granted == &normalized => {
+                        Some(normalized.clone())
+                    }
+                    // フォルダの中のファイルは、トークンで指定されたときと同じ確認をする
+                    Grant::Directory {
+                        path: granted,
+                        access: granted_access,
+                    } if *granted_access == access
+                        && requested.parent().map(normalize).as_ref() == Some(granted) =>
+                    {
+                        let name = requested.file_name()?.to_str()?;
+                        in_directory(granted, name).ok().flatten()
+                    }
+                    _ => None,
+                })
+            }
+        };
+
+        path.ok_or_else(|| {
+            IpcError::new(
+                ErrorCode::PermissionDenied,
+                "the file was not picked by the user in this session",
+            )
+        })
+    }
+}
+
+// フォルダ直下のnameを解決する。シンボリックリンクなどでフォルダの外に出る場合はNone。
+fn in_directory(directory: &Path, name: &str) -> Result<Option<PathBuf>, IpcError> {
+    let mut components = Path::new(name).components();
+    let (Some(Component::Normal(name)), None) = (components.next(), components.next()) else {
+        return Err(IpcError::new(
+            ErrorCode::InvalidArgument,
+            format!("invalid file name: {}", name),
+        ));
+    };
+    let Ok(directory) = std::fs::canonicalize(directory) else {
+        return Ok(None);
+    };
+    let path = directory.join(name);
+    // 既にあるもの（リンク切れのシンボリックリンクを含む）はリンク先で確認する。
+    // まだないファイルは、正規化したフォルダの直下に作られる
+    let path = match std::fs::symlink_metadata(&path) {
+        Ok(_) => std::fs::canonicalize(&path).ok(),
+        Err(_) => Some(path),
+    };
+    Ok(path.filter(|path| path.starts_with(&directory)))
+}
+
+// 保存先のファイルはまだ存在しないことがあるので、その場合は親ディレクトリで正規化する
+fn normalize(path: &Path) -> PathBuf {
+    if let Ok(path) = std::fs::canonicalize(path) {
+        return path;
+    }
+    match (path.parent(), path.file_name()) {
+        (Some(parent), Some(file_name)) => std::fs::canonicalize(parent)
+            .map(|parent| parent.join(file_name))
+            .unwrap_or_else(|_| path.to_path_buf()),
+        _ => path.to_path_buf(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // テストごとに別のフォルダを作り、終わったら消す
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new() -> Self {
+            let path = std::env::temp_dir().join(format!("vvvst-sandbox-{}", random_hex()));
+            std::fs::create_dir_all(path.join("granted")).unwrap();
+            std::fs::write(path.join("granted").join("inside.txt"), "inside").unwrap();
+            std::fs::write(path.join("outside.txt"), "outside").unwrap();
+            Self(path)
+        }
+
+        fn granted(&self) -> PathBuf {
+            self.0.join("granted")
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn in_directory_ref(token: &str, name: &str) -> FileRef {
+        FileRef::InDirectory {
+            token: token.to_string(),
+            name: name.to_string(),
+        }
+    }
+
+    fn code(result: Result<PathBuf, IpcError>) -> ErrorCode {
+        result.unwrap_err().code
+    }
+
+    #[test]
+    fn directory_allows_direct_children() {
+        let dir = TempDir::new();
+        let mut access = FileAccess::default();
+        let token = access.grant_directory(&dir.granted(), Access::Write).token;
+
+        let path = access
+            .resolve(&in_directory_ref(&token, "inside.txt"), Access::Write)
+            .unwrap();
+        assert_eq!(path, normalize(&dir.granted().join("inside.txt")));
+        // まだないファイルにも書き込める
+        let path = access
+            .resolve(&in_directory_ref(&token, "new.txt"), Access::Write)
+            .unwrap();
+        assert_eq!(path, normalize(&dir.granted()).join("new.txt"));
+    }
+
+    #[test]
+    fn directory_rejects_traversal_and_absolute_names() {
+        let dir = TempDir::new();
+        let mut access = FileAccess::default();
+        let token = access.grant_directory(&dir.granted(), Access::Read).token;
+
+        for name in [
+            "../outside.txt",
+            "..",
+            "sub/inside.txt",
+            "",
+            &dir.0.join("outside.txt").to_string_lossy(),
+        ] {
+            assert_eq!(
+                code(access.resolve(&in_directory_ref(&token, name), Access::Read)),
+                ErrorCode::InvalidArgument,
+                "{}",
+                name
+            );
+        }
+    }
+
+    #[test]
+    fn wrong_token_or_access_is_denied() {
+        let dir = TempDir::new();
+        let mut access = FileAccess::default();
+        let directory = access.grant_directory(&dir.granted(), Access::Read).token;
+        let file = access.grant(&dir.0.join("outside.txt"), Access::Read).token;
+
+        assert_eq!(
+            code(access.resolve(&in_directory_ref(&directory, "inside.txt"), Access::Write)),
+            ErrorCode::PermissionDenied
+        );
+        assert_eq!(
+            code(access.resolve(&in_directory_ref("unknown", "inside.txt"), Access::Read)),
+            ErrorCode::PermissionDenied
+        );
+        // ファイルのトークンではフォルダの中は解決できない
+        assert_eq!(
+            code(access.resolve(&in_directory_ref(&file, "inside.txt"), Access::Read)),
+            ErrorCode::PermissionDenied
+        );
+        assert_eq!(
+            code(access.resolve(&FileRef::Token { token: file }, Access::Write)),
+            ErrorCode::PermissionDenied
+        );
+        assert_eq!(
+            code(access.resolve(&FileRef::Token { token: directory }, Access::Read)),
+            ErrorCode::PermissionDenied
+        );
+    }
+
+    #[test]
+    fn path_form() {
+        let dir = TempDir::new();
+        let mut access = FileAccess::default();
+        access.grant_directory(&dir.granted(), Access::Read);
+        let path = |path: PathBuf| FileRef::Path(path.to_string_lossy().to_string());
+
+        assert!(access
+            .resolve(&path(dir.granted().join("inside.txt")), Access::Read)
+            .is_ok());
+        assert!(access
+            .resolve(
+                &path(dir.granted().join("..").join("outside.txt")),
+                Access::Read
+            )
+            .is_err());
+        assert!(access
+            .resolve(&path(dir.0.join("outside.txt")), Access::Read)
+            .is_err());
+        assert!(access
+            .resolve(&path(dir.granted().join("inside.txt")), Access::Write)
+            .is_err());
+
+        access.grant(&dir.0.join("outside.txt"), Access::Read);
+        assert!(access
+            .resolve(&path(dir.0.join("outside.txt")), Access::Read)
+            .is_ok());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlinks_out_of_directory_are_denied() {
+        use std::os::unix::fs::symlink;
+
+        let dir = TempDir::new();
+        symlink(dir.0.join("outside.txt"), dir.granted().join("escape.txt")).unwrap();
+        symlink(
+            dir.0.join("missing.txt"),
+            dir.granted().join("dangling.txt"),
+        )
+        .unwrap();
+        symlink(
+            dir.granted().join("inside.txt"),
+            dir.granted().join("alias.txt"),
+        )
+        .unwrap();
+        let mut access = FileAccess::default();
+        let token = access.grant_directory(&dir.granted(), Access::Write).token;
+
+        for name in ["escape.txt", "dangling.txt"] {
+            assert_eq!(
+                code(access.resolve(&in_directory_ref(&token, name), Access::Write)),
+                ErrorCode::PermissionDenied,
+                "{}",
+                name
+            );
+            let path = FileRef::Path(dir.granted().join(name).to_string_lossy().to_string());
+            assert_eq!(
+                code(access.resolve(&path, Access::Write)),
+                ErrorCode::PermissionDenied,
+                "{}",
+                name
+            );
+        }
+        assert_eq!(
+            access
+                .resolve(&in_directory_ref(&token, "alias.txt"), Access::Write)
+                .unwrap(),
+            normalize(&dir.granted().join("inside.txt"))
+        );
+    }
+}
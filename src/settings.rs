@@ -0,0 +1,87 @@
+use crate::models::DialogPurpose;
+use crate::utils::data_directory;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{LazyLock, Mutex};
+use tracing::warn;
+
+// プロジェクトではなくユーザーごとの設定。DAWの状態には保存せず、全インスタンスで共有する。
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Settings {
+    // ダイアログの用途ごとに、最後に使ったフォルダ
+    #[serde(default)]
+    dialog_directories: HashMap<DialogPurpose, PathBuf>,
+}
+
+static SETTINGS: LazyLock<Mutex<Settings>> = LazyLock::new(|| Mutex::new(load()));
+
+fn path() -> PathBuf {
+    data_directory().join("vvvst").join("settings.json")
+}
+
+fn load() -> Settings {
+    match std::fs::read(path()) {
+        Ok(data) => serde_json::from_slice(&data).unwrap_or_else(|err| {
+            warn!("failed to parse settings: {}", err);
+            Settings::default()
+        }),
+        Err(_) => Settings::default(),
+    }
+}
+
+fn save(settings: &Settings) -> anyhow::Result<()> {
+    let path = path();
+    if let Some(directory) = path.parent() {
+        std::fs::create_dir_all(directory)?;
+    }
+    let temporary = path.with_extension("json.tmp");
+    std::fs::write(&temporary, serde_json::to_vec_pretty(settings)?)?;
+    std::fs::rename(&temporary, &path)?;
+    Ok(())
+}
+
+/// ダイアログを開くフォルダ。startingDirectoryが指定されていなければ、
+/// 同じ用途で前回使ったフォルダから始める。
+pub fn dialog_directory(
+    purpose: Option<DialogPurpose>,
+    starting_directory: &Option<String>,
+) -> Option<PathBuf> {
+    if let Some(starting_directory) = starting_directory {
+        return Some(PathBuf::from(starting_directory));
+    }
+    SETTINGS
+        .lock()
+        .unwrap()
+        .dialog_directories
+        .get(&purpose?)
+        .cloned()
+}
+
+pub fn remember_directory(purpose: Option<DialogPurpose>, directory: &Path) {
+    let Some(purpose) = purpose else {
+        return;
+    };
+    let mut settings = SETTINGS.lock().unwrap();
+    if settings
+        .dialog_directories
+        .get(&purpose)
+        .map(PathBuf::as_path)
+        == Some(directory)
+    {
+        return;
+    }
+    settings
+        .dialog_directories
+        .insert(purpose, directory.to_path_buf());
+    if let Err(err) = save(&settings) {
+        warn!("failed to save settings: {:?}", err);
+    }
+}
+
+pub fn remember_file_directory(purpose: Option<DialogPurpose>, file: &Path) {
+    if let Some(directory) = file.parent() {
+        remember_directory(purpose, directory);
+    }
+}
@@ -0,0 +1,55 @@
+use crate::RUNTIME;
+use std::future::Future;
+use std::sync::Mutex as StdMutex;
+use tokio::task::AbortHandle;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TaskKind {
+    // ミックスの作り直し。deactivateで止める
+    Remix,
+    // エディタからのリクエストの処理
+    Request,
+    // 自動保存など、インスタンスが生きている間ずっと動くもの
+    Background,
+}
+
+// インスタンスごとに起動したタスク。RUNTIMEは全インスタンスで共有しているので、
+// プラグインが破棄されたらそのインスタンスのタスクだけを止める。
+#[derive(Debug, Default)]
+pub struct Tasks {
+    handles: StdMutex<Vec<(TaskKind, AbortHandle)>>,
+}
+
+impl Tasks {
+    pub fn spawn<F>(&self, kind: TaskKind, future: F) -> AbortHandle
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        let handle = RUNTIME.spawn(future).abort_handle();
+        let mut handles = self.handles.lock().unwrap();
+        handles.retain(|(_, handle)| !handle.is_finished());
+        handles.push((kind, handle.clone()));
+        handle
+    }
+
+    pub fn abort(&self, kind: TaskKind) {
+        self.handles.lock().unwrap().retain(|(task_kind, handle)| {
+            if *task_kind == kind {
+                handle.abort();
+            }
+            *task_kind != kind
+        });
+    }
+
+    pub fn abort_all(&self) {
+        for (_, handle) in self.handles.lock().unwrap().drain(..) {
+            handle.abort();
+        }
+    }
+}
+
+impl Drop for Tasks {
+    fn drop(&mut self) {
+        self.abort_all();
+    }
+}
@@ -0,0 +1,275 @@
+use crate::error::{ErrorCode, IpcError};
+use crate::models::{SingingVoiceKey, VoiceChunk, VoiceUploadStatus};
+use std::collections::{BTreeMap, HashMap};
+use std::time::{Duration, Instant};
+
+// 受信途中のチャンクとして保持できる合計サイズ。これを超えるチャンクは拒否し、
+// エディタ側には完了済みの音声が出るまで待ってもらう。
+pub const MAX_PENDING_BYTES: usize = 256 * 1024 * 1024;
+// 1つの音声のチャンク数の上限。64KiBずつ送れば受信途中の上限いっぱいまで使える数にする。
+// 届いていないチャンクの一覧を毎回作るので、ここで大きさを抑える
+pub const MAX_CHUNKS: usize = MAX_PENDING_BYTES / (64 * 1024);
+// この時間チャンクが届かなかった音声は、エディタが送るのをやめたものとして捨てる
+pub const UPLOAD_TIMEOUT: Duration = Duration::from_secs(60);
+
+#[derive(Debug)]
+struct PendingVoice {
+    total_chunks: usize,
+    chunks: BTreeMap<usize, Vec<u8>>,
+    updated_at: Instant,
+}
+
+impl PendingVoice {
+    fn size(&self) -> usize {
+        self.chunks.values().map(Vec::len).sum()
+    }
+
+    fn missing_chunks(&self) -> Vec<usize> {
+        (0..self.total_chunks)
+            .filter(|index| !self.chunks.contains_key(index))
+            .collect()
+    }
+}
+
+#[derive(Debug)]
+pub struct VoiceUploads {
+    pending: HashMap<SingingVoiceKey, PendingVoice>,
+    pending_bytes: usize,
+    max_pending_bytes: usize,
+}
+
+impl Default for VoiceUploads {
+    fn default() -> Self {
+        Self {
+            pending: HashMap::new(),
+            pending_bytes: 0,
+            max_pending_bytes: MAX_PENDING_BYTES,
+        }
+    }
+}
+
+impl VoiceUploads {
+    /// チャンクを受け取る。全てのチャンクが揃ったら結合した音声を返す。
+    pub fn receive(
+        &mut self,
+        chunk: &VoiceChunk,
+        data: Vec<u8>,
+    ) -> anyhow::Result<(VoiceUploadStatus, Option<Vec<u8>>)> {
+        anyhow::ensure!(
+            chunk.total_chunks > 0,
+            IpcError::new(ErrorCode::InvalidArgument, "totalChunks must be positive")
+        );
+        anyhow::ensure!(
+            chunk.total_chunks <= MAX_CHUNKS,
+            IpcError::new(
+                ErrorCode::InvalidArgument,
+                format!(
+                    "totalChunks {} is too large (max: {})",
+                    chunk.total_chunks, MAX_CHUNKS
+                )
+            )
+        );
+        anyhow::ensure!(
+            chunk.index < chunk.total_chunks,
+            IpcError::new(
+                ErrorCode::InvalidArgument,
+                format!(
+                    "chunk index {} is out of range (total: {})",
+                    chunk.index, chunk.total_chunks
+                )
+            )
+        );
+
+        let now = Instant::now();
+        self.evict_stale(now);
+
+        let pending = self
+            .pending
+            .entry(chunk.voice.clone())
+            .or_insert_with(|| PendingVoice {
+                total_chunks: chunk.total_chunks,
+                chunks: BTreeMap::new(),
+                updated_at: now,
+            });
+        if pending.total_chunks != chunk.total_chunks {
+            // 音声が作り直された場合などは最初から受け取り直す
+            self.pending_bytes -= pending.size();
+            pending.total_chunks = chunk.total_chunks;
+            pending.chunks.clear();
+        }
+
+        let previous_len = pending.chunks.get(&chunk.index).map_or(0, Vec::len);
+        anyhow::ensure!(
+            self.pending_bytes - previous_len + data.len() <= self.max_pending_bytes,
+            IpcError::new(
+                ErrorCode::Busy,
+                "upload buffer is full, retry after pending uploads complete"
+            )
+            .with_details(serde_json::json!({
+                "pendingBytes": self.pending_bytes,
+                "maxPendingBytes": self.max_pending_bytes,
+            }))
+        );
+        self.pending_bytes = self.pending_bytes - previous_len + data.len();
+        pending.chunks.insert(chunk.index, data);
+        pending.updated_at = now;
+
+        let missing_chunks = pending.missing_chunks();
+        if !missing_chunks.is_empty() {
+            return Ok((
+                VoiceUploadStatus {
+                    total_chunks: chunk.total_chunks,
+                    missing_chunks,
+                    complete: false,
+                },
+                None,
+            ));
+        }
+
+        let pending = self
+            .pending
+            .remove(&chunk.voice)
+            .expect("pending voice should exist");
+        self.pending_bytes -= pending.size();
+        let voice = pending.chunks.into_values().flatten().collect();
+        Ok((
+            VoiceUploadStatus {
+                total_chunks: chunk.total_chunks,
+                missing_chunks: vec![],
+                complete: true,
+            },
+            Some(voice),
+        ))
+    }
+
+    pub fn status(&mut self, voice: &SingingVoiceKey) -> Option<VoiceUploadStatus> {
+        self.evict_stale(Instant::now());
+        self.pending.get(voice).map(|pending| VoiceUploadStatus {
+            total_chunks: pending.total_chunks,
+            missing_chunks: pending.missing_chunks(),
+            complete: false,
+        })
+    }
+
+    /// 受信途中の音声を捨てる。音声がまとめて送られてきたときなどに使う。
+    pub fn remove(&mut self, voice: &SingingVoiceKey) {
+        if let Some(pending) = self.pending.remove(voice) {
+            self.pending_bytes -= pending.size();
+        }
+    }
+
+    /// エディタが閉じられたときなど、受信途中の音声を全て捨てる。
+    pub fn clear(&mut self) {
+        self.pending.clear();
+        self.pending_bytes = 0;
+    }
+
+    fn evict_stale(&mut self, now: Instant) {
+        let pending_bytes = &mut self.pending_bytes;
+        self.pending.retain(|_, pending| {
+            let alive = now.saturating_duration_since(pending.updated_at) < UPLOAD_TIMEOUT;
+            if !alive {
+                *pending_bytes -= pending.size();
+            }
+            alive
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chunk(voice: &str, index: usize, total_chunks: usize) -> VoiceChunk {
+        VoiceChunk {
+            voice: SingingVoiceKey(voice.to_string()),
+            index,
+            total_chunks,
+            data: String::new(),
+        }
+    }
+
+    #[test]
+    fn out_of_order_chunks() {
+        let mut uploads = VoiceUploads::default();
+        let (status, voice) = uploads.receive(&chunk("a", 2, 3), vec![5, 6]).unwrap();
+        assert_eq!(status.missing_chunks, vec![0, 1]);
+        assert!(voice.is_none());
+        uploads.receive(&chunk("a", 0, 3), vec![1, 2]).unwrap();
+        let (status, voice) = uploads.receive(&chunk("a", 1, 3), vec![3, 4]).unwrap();
+        assert!(status.complete);
+        assert_eq!(voice.unwrap(), vec![1, 2, 3, 4, 5, 6]);
+        assert_eq!(uploads.pending_bytes, 0);
+    }
+
+    #[test]
+    fn duplicate_chunks() {
+        let mut uploads = VoiceUploads::default();
+        uploads.receive(&chunk("a", 0, 2), vec![1, 2, 3]).unwrap();
+        // 再送されたチャンクは前のものを置き換え、二重に数えない
+        uploads.receive(&chunk("a", 0, 2), vec![1, 2]).unwrap();
+        assert_eq!(uploads.pending_bytes, 2);
+        let (_, voice) = uploads.receive(&chunk("a", 1, 2), vec![3]).unwrap();
+        assert_eq!(voice.unwrap(), vec![1, 2, 3]);
+        assert_eq!(uploads.pending_bytes, 0);
+    }
+
+    #[test]
+    fn byte_cap() {
+        let mut uploads = VoiceUploads {
+            max_pending_bytes: 4,
+            ..Default::default()
+        };
+        uploads.receive(&chunk("a", 0, 2), vec![0; 3]).unwrap();
+        let err = uploads.receive(&chunk("b", 0, 2), vec![0; 2]).unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<IpcError>().unwrap().code,
+            ErrorCode::Busy
+        );
+        // 完了するチャンクでなくても、置き換えで上限に収まるなら受け取る
+        uploads.receive(&chunk("a", 0, 2), vec![0; 4]).unwrap();
+        assert_eq!(uploads.pending_bytes, 4);
+    }
+
+    #[test]
+    fn too_many_chunks() {
+        let mut uploads = VoiceUploads::default();
+        let err = uploads
+            .receive(&chunk("a", 0, 1_000_000_000_000), vec![0])
+            .unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<IpcError>().unwrap().code,
+            ErrorCode::InvalidArgument
+        );
+        assert!(uploads.pending.is_empty());
+
+        let (status, _) = uploads
+            .receive(&chunk("a", 0, MAX_CHUNKS), vec![0])
+            .unwrap();
+        assert_eq!(status.missing_chunks.len(), MAX_CHUNKS - 1);
+    }
+
+    #[test]
+    fn abandoned_uploads_are_evicted() {
+        let mut uploads = VoiceUploads::default();
+        uploads.receive(&chunk("a", 0, 2), vec![0; 3]).unwrap();
+        uploads.evict_stale(Instant::now() + UPLOAD_TIMEOUT);
+        assert!(uploads.status(&SingingVoiceKey("a".to_string())).is_none());
+        assert_eq!(uploads.pending_bytes, 0);
+    }
+
+    #[test]
+    fn replaced_and_cleared_uploads_release_bytes() {
+        let mut uploads = VoiceUploads::default();
+        uploads.receive(&chunk("a", 0, 2), vec![0; 3]).unwrap();
+        // 作り直された音声はチャンク数が変わる
+        uploads.receive(&chunk("a", 0, 3), vec![0; 1]).unwrap();
+        assert_eq!(uploads.pending_bytes, 1);
+        uploads.receive(&chunk("b", 0, 2), vec![0; 2]).unwrap();
+        uploads.remove(&SingingVoiceKey("b".to_string()));
+        assert_eq!(uploads.pending_bytes, 1);
+        uploads.clear();
+        assert_eq!(uploads.pending_bytes, 0);
+        assert!(uploads.pending.is_empty());
+    }
+}
@@ -1,44 +1,132 @@
 use nih_plug::params::persist::PersistentField;
 use serde::{Deserialize, Serialize};
-use std::ops::Deref;
-use std::sync::Arc;
-use tokio::sync::Mutex;
-
-use crate::RUNTIME;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use tokio::sync::Notify;
 
+// ホストの保存・読み込みのスレッドから呼ばれるので、非同期のロックは使わない。
+// ガードは非同期タスクをまたいで持てない（Sendではない）ので、ロックは必ず短時間で外れる。
 #[derive(Debug)]
-pub struct TokioMutexParam<T: Send + Sync> {
+pub struct MutexParam<T: Send + Sync> {
     inner: Arc<Mutex<T>>,
+    // ホストから状態が復元されたときに通知する
+    on_set: Option<Arc<Notify>>,
+}
+
+impl<T: Send + Sync> MutexParam<T> {
+    pub fn with_notify(on_set: Arc<Notify>) -> Self
+    where
+        T: Default,
+    {
+        Self {
+            inner: Arc::new(Mutex::new(T::default())),
+            on_set: Some(on_set),
+        }
+    }
+
+    // 他のスレッドでパニックしても、ホストの保存まで巻き込まないように中身はそのまま使う
+    pub fn lock(&self) -> MutexGuard<'_, T> {
+        self.inner.lock().unwrap_or_else(PoisonError::into_inner)
+    }
 }
 
-impl<'a, T: Send + Sync + Serialize + Deserialize<'a> + Default> Default for TokioMutexParam<T> {
+impl<'a, T: Send + Sync + Serialize + Deserialize<'a> + Default> Default for MutexParam<T> {
     fn default() -> Self {
         Self {
             inner: Arc::new(Mutex::new(T::default())),
+            on_set: None,
         }
     }
 }
 
-impl<'a, T: Send + Sync + Serialize + Deserialize<'a>> PersistentField<'a, T>
-    for TokioMutexParam<T>
-{
+impl<'a, T: Send + Sync + Serialize + Deserialize<'a>> PersistentField<'a, T> for MutexParam<T> {
     fn set(&self, value: T) {
-        let mut inner = RUNTIME.block_on(self.inner.lock());
-        *inner = value;
+        *self.lock() = value;
+        if let Some(on_set) = &self.on_set {
+            on_set.notify_one();
+        }
     }
     fn map<F, R>(&self, f: F) -> R
     where
         F: Fn(&T) -> R,
     {
-        let inner = RUNTIME.block_on(self.inner.lock());
-        f(&*inner)
+        f(&*self.lock())
     }
 }
 
-impl<'a, T: Send + Sync + Serialize + Deserialize<'a>> Deref for TokioMutexParam<T> {
-    type Target = Arc<Mutex<T>>;
+// トークンやIDに使う。推測されないように、OSの暗号学的に安全な乱数から128ビット作る
+pub fn random_hex() -> String {
+    let mut bytes = [0u8; 16];
+    getrandom::fill(&mut bytes).expect("the OS random number generator should be available");
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+// OSごとのアプリケーションデータの置き場所。tempは再起動で消えることがあるので使わない。
+pub fn data_directory() -> PathBuf {
+    let directory = if cfg!(target_os = "windows") {
+        std::env::var_os("LOCALAPPDATA").map(PathBuf::from)
+    } else if cfg!(target_os = "macos") {
+        std::env::var_os("HOME").map(|home| {
+            PathBuf::from(home)
+                .join("Library")
+                .join("Application Support")
+        })
+    } else {
+        std::env::var_os("XDG_DATA_HOME")
+            .map(PathBuf::from)
+            .or_else(|| {
+                std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".local/share"))
+            })
+    };
+    directory.unwrap_or_else(std::env::temp_dir)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn set_and_map_inside_runtime() {
+        // 以前はblock_onを使っていたので、ランタイムの中から呼ぶとパニックしていた
+        let param = MutexParam::<Vec<u32>>::default();
+        crate::RUNTIME.block_on(async {
+            param.set(vec![1, 2, 3]);
+            assert_eq!(param.map(|value| value.clone()), vec![1, 2, 3]);
+        });
+    }
+
+    #[test]
+    fn set_notifies() {
+        let notify = Arc::new(Notify::new());
+        let param = MutexParam::<String>::with_notify(Arc::clone(&notify));
+        param.set("project".to_string());
+        crate::RUNTIME.block_on(async {
+            tokio::time::timeout(Duration::from_secs(1), notify.notified())
+                .await
+                .expect("set should notify");
+        });
+        assert_eq!(*param.lock(), "project");
+    }
+
+    #[test]
+    fn random_hex_is_unique() {
+        let value = random_hex();
+        assert_eq!(value.len(), 32);
+        assert!(value.chars().all(|c| c.is_ascii_hexdigit()));
+        assert_ne!(value, random_hex());
+    }
 
-    fn deref(&self) -> &Self::Target {
-        &self.inner
+    #[test]
+    fn lock_survives_poison() {
+        let param = Arc::new(MutexParam::<u64>::default());
+        let poisoned = Arc::clone(&param);
+        let _ = std::thread::spawn(move || {
+            let _guard = poisoned.lock();
+            panic!("poison the lock");
+        })
+        .join();
+        param.set(1);
+        assert_eq!(param.map(|value| *value), 1);
     }
 }